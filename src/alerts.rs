@@ -0,0 +1,214 @@
+use crate::audit::Actor;
+use crate::config::AlertsConfig;
+use crate::state::{AppState, ChargeMode};
+use crate::validation::validate_mode;
+use chrono::{DateTime, Local};
+use rocket::log::private::{info, warn};
+use rocket::tokio;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// An alert from a CAP document or an RSS or Atom feed
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alert {
+    pub title: String,
+    /// The event, headline, title and categories, to match the configured events against
+    pub event: String,
+    /// Everything that names the affected area, to match the configured regions against
+    pub area: String,
+    pub expires: Option<DateTime<Local>>,
+}
+
+impl Alert {
+    /// Whether the alert has not expired and names one of the regions and, if any are given, one of the events
+    pub fn matches(&self, config: &AlertsConfig, now: DateTime<Local>) -> bool {
+        let area = self.area.to_lowercase();
+        let event = self.event.to_lowercase();
+        self.expires.is_none_or(|expires| expires > now)
+            && config
+                .regions
+                .iter()
+                .any(|region| area.contains(&region.to_lowercase()))
+            && (config.events.is_empty()
+                || config
+                    .events
+                    .iter()
+                    .any(|name| event.contains(&name.to_lowercase())))
+    }
+}
+
+/// The texts of all elements under `node` with one of the local `names`, in document order
+fn texts<'a>(node: roxmltree::Node<'a, 'a>, names: &[&str]) -> Vec<&'a str> {
+    node.descendants()
+        .filter(|child| names.contains(&child.tag_name().name()))
+        .filter_map(|child| child.text())
+        .map(str::trim)
+        .filter(|text| !text.is_empty())
+        .collect()
+}
+
+/// The alerts of a CAP document (`info`), an RSS feed (`item`) or an Atom feed (`entry`),
+/// including feeds with CAP fields in their entries
+pub fn parse_alerts(xml: &str) -> Result<Vec<Alert>, roxmltree::Error> {
+    let document = roxmltree::Document::parse(xml)?;
+    let alerts = document
+        .descendants()
+        .filter(|node| matches!(node.tag_name().name(), "info" | "item" | "entry"))
+        .map(|node| {
+            let event = texts(node, &["event", "headline", "title", "category"]);
+            Alert {
+                title: event.first().unwrap_or(&"").to_string(),
+                event: event.join("\n"),
+                area: texts(
+                    node,
+                    &["areaDesc", "value", "title", "description", "summary"],
+                )
+                .join("\n"),
+                expires: texts(node, &["expires"])
+                    .first()
+                    .and_then(|expires| DateTime::parse_from_rfc3339(expires).ok())
+                    .map(|expires| expires.with_timezone(&Local)),
+            }
+        })
+        .collect();
+    Ok(alerts)
+}
+
+/// Poll the alert feed and switch to the backup mode while a matching alert is active
+pub async fn run(state: Arc<AppState>, config: AlertsConfig) {
+    info!(target: "app", "Polling weather alerts from {} every {} s", config.url, config.interval);
+    let client = reqwest::Client::new();
+    let mut active = false;
+    loop {
+        match fetch(&client, &config.url).await {
+            Ok(alerts) => active = check(&state, &config, &alerts, active).await,
+            Err(e) => warn!("Failed to poll weather alerts: {:?}", e),
+        }
+        tokio::time::sleep(Duration::from_secs(config.interval)).await;
+    }
+}
+
+async fn fetch(
+    client: &reqwest::Client,
+    url: &str,
+) -> Result<Vec<Alert>, Box<dyn std::error::Error + Send + Sync>> {
+    let body = client
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+    Ok(parse_alerts(&body)?)
+}
+
+/// Set the backup mode for a matching alert, and the default mode again once no alert matches.
+/// `active` tells whether an alert set the backup mode; the result is the same for the next check.
+/// A mode set by someone else in the meantime is left alone.
+pub async fn check(
+    state: &Arc<AppState>,
+    config: &AlertsConfig,
+    alerts: &[Alert],
+    active: bool,
+) -> bool {
+    let now = Local::now();
+    let alert = alerts.iter().find(|alert| alert.matches(config, now));
+    let in_backup = matches!(*state.current_mode.lock().await, ChargeMode::Backup { .. });
+    match alert {
+        Some(_) if active || in_backup => active,
+        Some(alert) => {
+            let charge_mode = ChargeMode::Backup {
+                reserve_soc: config.reserve_soc,
+            };
+            if let Err(e) = validate_mode(&charge_mode, &state.config()) {
+                warn!(
+                    "Ignoring weather alert, invalid backup mode: {:?}",
+                    e.errors
+                );
+                return false;
+            }
+            info!(target: "app", "Weather alert: {}", alert.title);
+            AppState::apply_mode(
+                state,
+                charge_mode.clone(),
+                Actor::controller(&format!("weather alert: {}", alert.title)),
+            )
+            .await;
+            if let Some(mqtt) = &state.mqtt {
+                mqtt.publish_mode(&charge_mode).await;
+            }
+            true
+        }
+        None if active && in_backup => {
+            info!(target: "app", "Weather alerts ended");
+            state
+                .reset_mode(Actor::controller("weather alerts ended"))
+                .await;
+            if let Some(mqtt) = &state.mqtt {
+                mqtt.publish_mode(&state.current_mode.lock().await.clone())
+                    .await;
+            }
+            false
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(regions: &[&str], events: &[&str]) -> AlertsConfig {
+        AlertsConfig {
+            url: "http://localhost/alerts".to_string(),
+            regions: regions.iter().map(|s| s.to_string()).collect(),
+            events: events.iter().map(|s| s.to_string()).collect(),
+            interval: 300,
+            reserve_soc: 100,
+        }
+    }
+
+    #[test]
+    fn test_parse_alerts() {
+        let cap = r#"<?xml version="1.0" encoding="UTF-8"?>
+<alert xmlns="urn:oasis:names:tc:emergency:cap:1.2">
+  <identifier>TEST-1</identifier>
+  <info>
+    <event>Severe Thunderstorm Warning</event>
+    <headline>Severe thunderstorms with damaging winds</headline>
+    <expires>2099-01-10T18:00:00+10:00</expires>
+    <area>
+      <areaDesc>Greater Sydney</areaDesc>
+      <geocode><valueName>AMOC-AreaCode</valueName><value>NSW_PW005</value></geocode>
+    </area>
+  </info>
+</alert>"#;
+        let alerts = parse_alerts(cap).unwrap();
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].title, "Severe Thunderstorm Warning");
+        assert!(alerts[0].area.contains("Greater Sydney"));
+        assert!(alerts[0].area.contains("NSW_PW005"));
+        assert!(alerts[0].expires.is_some());
+
+        let now = Local::now();
+        assert!(alerts[0].matches(&config(&["sydney"], &[]), now));
+        assert!(alerts[0].matches(&config(&["NSW_PW005"], &["thunderstorm"]), now));
+        assert!(!alerts[0].matches(&config(&["Hunter"], &[]), now));
+        assert!(!alerts[0].matches(&config(&["Sydney"], &["flood"]), now));
+
+        let rss = r#"<rss version="2.0"><channel><title>Warnings</title>
+  <item><title>Flood Warning for the Hunter River</title><description>Minor flooding</description></item>
+  <item><title>Storm Warning for Greater Sydney</title></item>
+</channel></rss>"#;
+        let alerts = parse_alerts(rss).unwrap();
+        assert_eq!(alerts.len(), 2);
+        assert!(alerts[0].matches(&config(&["Hunter"], &["flood"]), now));
+        assert!(!alerts[1].matches(&config(&["Hunter"], &[]), now));
+
+        let expired = Alert {
+            expires: Some(now - chrono::Duration::minutes(1)),
+            ..alerts[1].clone()
+        };
+        assert!(!expired.matches(&config(&["Sydney"], &[]), now));
+    }
+}
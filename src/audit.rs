@@ -0,0 +1,104 @@
+use crate::auth::authenticate;
+use crate::ecos::data_models::{ChargeModeSettingsRequest, EcosResponse};
+use crate::state::{AppState, ChargeMode};
+use crate::storage::Timestamped;
+use chrono::{DateTime, Local};
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::serde::{Deserialize, Serialize};
+use rocket::FromFormField;
+use std::sync::Arc;
+
+/// Who caused an audited event
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "rocket::serde", tag = "type", rename_all = "kebab-case")]
+pub enum Actor {
+    /// A request to the HTTP API, with the name of the API key used
+    Api {
+        address: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        key: Option<String>,
+    },
+    /// A command received over MQTT
+    Mqtt { topic: String },
+    /// The controller itself, e.g. the control loop or an expiring mode
+    Controller { reason: String },
+}
+
+impl Actor {
+    pub fn controller(reason: &str) -> Self {
+        Actor::Controller {
+            reason: reason.to_string(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "rocket::serde", tag = "kind", rename_all = "kebab-case")]
+pub enum AuditEvent {
+    /// The requested charge mode
+    ModeChange { mode: ChargeMode },
+    /// The exact settings posted to ECOS for the current mode, and the outcome
+    SettingsWrite {
+        mode: ChargeMode,
+        request: Box<ChargeModeSettingsRequest>,
+        response: Option<EcosResponse>,
+        error: Option<String>,
+    },
+    /// A reload of the config file, with the reason it was rejected
+    ConfigReload { error: Option<String> },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromFormField)]
+pub enum AuditKind {
+    #[field(value = "mode-change")]
+    ModeChange,
+    #[field(value = "settings-write")]
+    SettingsWrite,
+    #[field(value = "config-reload")]
+    ConfigReload,
+}
+
+impl AuditEvent {
+    pub fn kind(&self) -> AuditKind {
+        match self {
+            AuditEvent::ModeChange { .. } => AuditKind::ModeChange,
+            AuditEvent::SettingsWrite { .. } => AuditKind::SettingsWrite,
+            AuditEvent::ConfigReload { .. } => AuditKind::ConfigReload,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct AuditEntry {
+    pub timestamp: DateTime<Local>,
+    pub actor: Actor,
+    #[serde(flatten)]
+    pub event: AuditEvent,
+}
+
+impl Timestamped for AuditEntry {
+    fn timestamp(&self) -> DateTime<Local> {
+        self.timestamp
+    }
+}
+
+/// Request guard identifying the API caller for the audit log
+pub struct Caller(pub Actor);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Caller {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let key = request
+            .rocket()
+            .state::<Arc<AppState>>()
+            .and_then(|state| authenticate(request, &state.auth))
+            .map(|api_key| api_key.name.clone());
+        Outcome::Success(Caller(Actor::Api {
+            address: request.client_ip().map(|ip| ip.to_string()),
+            key,
+        }))
+    }
+}
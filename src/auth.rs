@@ -0,0 +1,69 @@
+use crate::config::{ApiKey, AuthConfig, Scope};
+use crate::state::AppState;
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub enum AuthError {
+    /// No key, or a key that is not configured
+    Unauthenticated,
+    /// A valid key without the required scope
+    Forbidden,
+}
+
+/// The configured key sent with the request, as `Authorization: Bearer <key>` or `X-API-Key: <key>`
+pub fn authenticate<'a>(request: &Request<'_>, config: &'a AuthConfig) -> Option<&'a ApiKey> {
+    let sent = request
+        .headers()
+        .get_one("Authorization")
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .or_else(|| request.headers().get_one("X-API-Key"))?
+        .trim();
+    config
+        .keys
+        .iter()
+        .find(|api_key| constant_time_eq(api_key.key.as_bytes(), sent.as_bytes()))
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn authorize(request: &Request<'_>, scope: Scope) -> Outcome<(), AuthError> {
+    let Some(state) = request.rocket().state::<Arc<AppState>>() else {
+        return Outcome::Error((Status::InternalServerError, AuthError::Unauthenticated));
+    };
+    if state.auth.keys.is_empty() && state.auth.open {
+        return Outcome::Success(());
+    }
+    match authenticate(request, &state.auth) {
+        Some(api_key) if api_key.scope >= scope => Outcome::Success(()),
+        Some(_) => Outcome::Error((Status::Forbidden, AuthError::Forbidden)),
+        None => Outcome::Error((Status::Unauthorized, AuthError::Unauthenticated)),
+    }
+}
+
+/// Guard for routes that only read, requiring a key with the `read` or `control` scope
+pub struct ReadAccess;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ReadAccess {
+    type Error = AuthError;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        authorize(request, Scope::Read).map(|_| ReadAccess)
+    }
+}
+
+/// Guard for routes that change the charge mode or the device settings, requiring a key with the `control` scope
+pub struct ControlAccess;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ControlAccess {
+    type Error = AuthError;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        authorize(request, Scope::Control).map(|_| ControlAccess)
+    }
+}
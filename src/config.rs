@@ -1,13 +1,62 @@
 #![allow(non_snake_case)]
-use crate::ecos::data_models::ChargeSchedule;
+use crate::ecos::client::EcosClient;
+use crate::ecos::data_models::{
+    ChargeModeSettingsPatch, ChargeModeSettingsRequest, ChargeSchedule,
+};
+use crate::make_struct_with_time_device_info;
 use rocket::serde::de::DeserializeOwned;
 use rocket::serde::Deserialize;
+use std::path::Path;
+use toml_edit::{Array, DocumentMut, InlineTable};
 
 #[derive(Deserialize)]
 #[serde(crate = "rocket::serde")]
 pub struct Config {
     pub ecos: EcosConfig,
     pub app: AppConfig,
+    #[serde(default)]
+    pub storage: StorageConfig,
+    #[serde(default)]
+    pub poller: PollerConfig,
+    pub mqtt: Option<MqttConfig>,
+    #[serde(default)]
+    pub auth: AuthConfig,
+    pub alerts: Option<AlertsConfig>,
+}
+
+/// API keys accepted by the HTTP API. Without any keys the API only runs with `open`.
+#[derive(Deserialize, Clone, Default)]
+#[serde(crate = "rocket::serde", default)]
+pub struct AuthConfig {
+    pub keys: Vec<ApiKey>,
+    /// Allow every request when no keys are configured, instead of refusing to start
+    pub open: bool,
+}
+
+impl AuthConfig {
+    /// An API without keys, open to anyone who can reach it
+    pub fn open() -> Self {
+        AuthConfig {
+            keys: vec![],
+            open: true,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(crate = "rocket::serde")]
+pub struct ApiKey {
+    pub name: String,
+    pub key: String,
+    pub scope: Scope,
+}
+
+/// What a key may do; `control` includes `read`
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+pub enum Scope {
+    Read,
+    Control,
 }
 
 #[derive(Deserialize)]
@@ -16,6 +65,114 @@ pub struct EcosConfig {
     pub user: String,
     pub password: String,
     pub base_url: String,
+    #[serde(default)]
+    pub cache: CacheConfig,
+}
+
+/// How long ECOS responses are cached, per endpoint. 0 disables caching.
+#[derive(Deserialize, Clone)]
+#[serde(crate = "rocket::serde", default)]
+pub struct CacheConfig {
+    pub devices: u64,              // in seconds
+    pub run_data: u64,             // in seconds
+    pub charge_mode_settings: u64, // in seconds
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            devices: 300,
+            run_data: 10,
+            charge_mode_settings: 60,
+        }
+    }
+}
+
+/// A CAP, RSS or Atom feed of weather alerts. The backup mode is set while an alert names one of
+/// the `regions` and, if any are given, one of the `events`.
+#[derive(Deserialize, Clone)]
+#[serde(crate = "rocket::serde")]
+pub struct AlertsConfig {
+    pub url: String,
+    pub regions: Vec<String>,
+    #[serde(default)]
+    pub events: Vec<String>,
+    #[serde(default = "AlertsConfig::default_interval")]
+    pub interval: u64, // in seconds
+    #[serde(default = "AlertsConfig::default_reserve_soc")]
+    pub reserve_soc: u8, // in percent
+}
+
+impl AlertsConfig {
+    fn default_interval() -> u64 {
+        300
+    }
+
+    fn default_reserve_soc() -> u8 {
+        100
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct StorageConfig {
+    pub data_dir: String,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        StorageConfig {
+            data_dir: "data".to_string(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct PollerConfig {
+    pub interval: u64, // in seconds
+}
+
+impl Default for PollerConfig {
+    fn default() -> Self {
+        PollerConfig { interval: 60 }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(crate = "rocket::serde", default)]
+pub struct MqttConfig {
+    pub host: String,
+    pub port: u16,
+    pub client_id: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub run_data_topic: String,
+    pub mode_topic: String,
+    pub command_topic: String,
+    pub discovery: bool, // publish Home Assistant discovery messages
+    pub discovery_prefix: String,
+    pub node_id: String,
+    pub controls_topic: String,
+}
+
+impl Default for MqttConfig {
+    fn default() -> Self {
+        MqttConfig {
+            host: "localhost".to_string(),
+            port: 1883,
+            client_id: "ecactus-controller".to_string(),
+            username: None,
+            password: None,
+            run_data_topic: "ecactus/run-data".to_string(),
+            mode_topic: "ecactus/charge-mode".to_string(),
+            command_topic: "ecactus/charge-mode/set".to_string(),
+            discovery: false,
+            discovery_prefix: "homeassistant".to_string(),
+            node_id: "ecactus".to_string(),
+            controls_topic: "ecactus/controls".to_string(),
+        }
+    }
 }
 
 #[derive(Deserialize)]
@@ -30,6 +187,43 @@ pub struct AppConfig {
     pub chargingList: Vec<ChargeSchedule>,
     pub dischargingList: Vec<ChargeSchedule>,
     pub epsBatteryMin: i32,
+    /// The rated power of the inverter in watts, which `maxFeedIn` is a percentage of
+    pub ratedPower: Option<u32>,
+    pub hysteresis: HysteresisConfig,
+    /// Build on the device settings from before the first mode instead of the values above,
+    /// and restore them when the modes end
+    pub preserveSettings: bool,
+}
+
+/// When the periodic modes skip writing settings that barely changed. A `power` of 0 disables it.
+#[derive(Deserialize, Clone)]
+#[serde(crate = "rocket::serde", default)]
+pub struct HysteresisConfig {
+    /// Smaller changes of the charge or discharge power are not written, in watts
+    pub power: u32,
+    /// How long the schedule windows last beyond the next check, in seconds,
+    /// so that the applied settings keep working while writes are skipped
+    pub hold: u64,
+}
+
+impl HysteresisConfig {
+    /// How long the windows last beyond the next check, in seconds: `hold`, or 0 when the hysteresis is disabled
+    pub fn window_hold(&self) -> u64 {
+        if self.power > 0 {
+            self.hold
+        } else {
+            0
+        }
+    }
+}
+
+impl Default for HysteresisConfig {
+    fn default() -> Self {
+        HysteresisConfig {
+            power: 0,
+            hold: 600,
+        }
+    }
 }
 
 impl AppConfig {
@@ -44,10 +238,43 @@ impl AppConfig {
             chargingList: vec![],
             dischargingList: vec![],
             epsBatteryMin: 10,
+            ratedPower: None,
+            hysteresis: HysteresisConfig::default(),
+            preserveSettings: false,
         }
     }
 }
 
+impl AppConfig {
+    /// Set the fields of `patch` as the defaults, as `write_app_settings` does in the file
+    pub fn apply_settings(&mut self, patch: &ChargeModeSettingsPatch) {
+        let mut request = self.settings_request();
+        patch.apply(&mut request);
+        self.chargeUseMode = request.chargeUseMode;
+        self.minCapacity = request.minCapacity;
+        self.maxFeedIn = request.maxFeedIn;
+        self.dischargeToGridFlag = request.dischargeToGridFlag;
+        self.chargingList = request.chargingList;
+        self.dischargingList = request.dischargingList;
+        self.epsBatteryMin = request.epsBatteryMin;
+    }
+
+    /// The device settings of the config, which the modes start from without `preserveSettings`
+    pub fn settings_request(&self) -> ChargeModeSettingsRequest {
+        make_struct_with_time_device_info!(
+            ChargeModeSettingsRequest,
+            deviceId: self.deviceId.clone(),
+            chargeUseMode: self.chargeUseMode,
+            minCapacity: self.minCapacity,
+            maxFeedIn: self.maxFeedIn,
+            dischargeToGridFlag: self.dischargeToGridFlag,
+            chargingList: self.chargingList.clone(),
+            dischargingList: self.dischargingList.clone(),
+            epsBatteryMin: self.epsBatteryMin
+        )
+    }
+}
+
 impl Default for AppConfig {
     fn default() -> Self {
         AppConfig::new()
@@ -58,3 +285,61 @@ pub fn read_config<T: DeserializeOwned>(path: &str) -> T {
     let content = std::fs::read_to_string(path).expect("Failed to read config file");
     toml::from_str(&content).expect("Failed to parse config")
 }
+
+/// Read the config file like `read_config`, returning the errors instead of panicking
+pub fn load_config<T: DeserializeOwned>(
+    path: &Path,
+) -> Result<T, Box<dyn std::error::Error + Send + Sync>> {
+    let content = std::fs::read_to_string(path)?;
+    Ok(toml::from_str(&content)?)
+}
+
+fn schedules_value(schedules: &[ChargeSchedule]) -> Array {
+    schedules
+        .iter()
+        .map(|schedule| {
+            let mut table = InlineTable::new();
+            table.insert("startHour", i64::from(schedule.startHour).into());
+            table.insert("startMinute", i64::from(schedule.startMinute).into());
+            table.insert("endHour", i64::from(schedule.endHour).into());
+            table.insert("endMinute", i64::from(schedule.endMinute).into());
+            table.insert("power", i64::from(schedule.power).into());
+            table.insert("abandonPv", i64::from(schedule.abandonPv).into());
+            table
+        })
+        .collect()
+}
+
+/// Write the fields of `patch` to the `[app]` table of the config file, keeping its comments and layout.
+/// The file is replaced in one rename, so a reader never sees half of it.
+pub fn write_app_settings(
+    path: &Path,
+    patch: &ChargeModeSettingsPatch,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut document: DocumentMut = std::fs::read_to_string(path)?.parse()?;
+    let app = document["app"]
+        .as_table_mut()
+        .ok_or("the config has no [app] table")?;
+    for (key, value) in [
+        ("chargeUseMode", patch.chargeUseMode),
+        ("minCapacity", patch.minCapacity),
+        ("maxFeedIn", patch.maxFeedIn),
+        ("dischargeToGridFlag", patch.dischargeToGridFlag),
+        ("epsBatteryMin", patch.epsBatteryMin),
+    ] {
+        if let Some(value) = value {
+            app[key] = toml_edit::value(i64::from(value));
+        }
+    }
+    if let Some(charging_list) = &patch.chargingList {
+        app["chargingList"] = toml_edit::value(schedules_value(charging_list));
+    }
+    if let Some(discharging_list) = &patch.dischargingList {
+        app["dischargingList"] = toml_edit::value(schedules_value(discharging_list));
+    }
+
+    let temp = path.with_extension("toml.tmp");
+    std::fs::write(&temp, document.to_string())?;
+    std::fs::rename(&temp, path)?;
+    Ok(())
+}
@@ -0,0 +1,156 @@
+use rocket::serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// Largest charge or discharge power the controller gives, in watts
+const MAX_OUTPUT: f32 = 5000.0;
+
+/// Tuning of the optional controller of the periodic modes. The defaults apply the computed power as it is.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, ToSchema)]
+#[serde(crate = "rocket::serde", default, deny_unknown_fields)]
+pub struct ControllerSettings {
+    /// Proportional gain on the difference between the computed and the applied power.
+    /// Below 1, the applied power approaches the computed one over several checks.
+    pub kp: f32,
+    /// Integral gain on the sum of the differences since the applied power was last on the other side,
+    /// to reach the computed power in fewer checks than `kp` alone
+    pub ki: f32,
+    /// Weight of each new reading in the moving average of the computed power, 1 for no smoothing
+    pub smoothing: f32,
+    /// Smaller changes are not applied, in watts
+    pub deadband: u32,
+    /// The largest change from one check to the next, in watts
+    pub max_step: Option<u32>,
+}
+
+impl Default for ControllerSettings {
+    fn default() -> Self {
+        ControllerSettings {
+            kp: 1.0,
+            ki: 0.0,
+            smoothing: 1.0,
+            deadband: 0,
+            max_step: None,
+        }
+    }
+}
+
+/// A PI controller between the power computed from each run data reading and the power that is applied.
+/// Without settings, the computed power passes through.
+#[derive(Debug, Default)]
+pub struct PowerController {
+    settings: Option<ControllerSettings>,
+    smoothed: Option<f32>,
+    integral: f32,
+    output: f32,
+}
+
+impl PowerController {
+    pub fn new(settings: Option<ControllerSettings>) -> Self {
+        PowerController {
+            settings,
+            ..PowerController::default()
+        }
+    }
+
+    /// New settings for an adjusted mode, keeping the applied power
+    pub fn set_settings(&mut self, settings: Option<ControllerSettings>) {
+        self.settings = settings;
+    }
+
+    /// The power to apply for the `computed` power, in watts; positive charges, negative discharges.
+    /// The applied power starts from 0 when the mode starts.
+    pub fn update(&mut self, computed: f32) -> f32 {
+        let Some(settings) = &self.settings else {
+            return computed;
+        };
+        let smoothed = self.smoothed.map_or(computed, |smoothed| {
+            smoothed + settings.smoothing * (computed - smoothed)
+        });
+        self.smoothed = Some(smoothed);
+
+        let error = smoothed - self.output;
+        if error.abs() < settings.deadband as f32 {
+            return self.output;
+        }
+        // the integral only speeds up the approach to the computed power: it restarts when the
+        // difference changes sign, and a step never goes past the computed power, so it cannot overshoot
+        if self.integral * error < 0.0 {
+            self.integral = 0.0;
+        }
+        self.integral = (self.integral + error).clamp(-MAX_OUTPUT, MAX_OUTPUT);
+        let mut step = (settings.kp * error + settings.ki * self.integral)
+            .clamp(error.min(0.0), error.max(0.0));
+        if let Some(max_step) = settings.max_step {
+            step = step.clamp(-(max_step as f32), max_step as f32);
+        }
+        self.output = (self.output + step).clamp(-MAX_OUTPUT, MAX_OUTPUT);
+        self.output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_power_controller() {
+        let mut controller = PowerController::new(None);
+        assert_eq!(controller.update(1234.0), 1234.0);
+
+        let mut controller = PowerController::new(Some(ControllerSettings::default()));
+        assert_eq!(controller.update(1000.0), 1000.0);
+        assert_eq!(controller.update(-500.0), -500.0);
+
+        let mut controller = PowerController::new(Some(ControllerSettings {
+            kp: 0.5,
+            deadband: 100,
+            max_step: Some(400),
+            ..ControllerSettings::default()
+        }));
+        assert_eq!(controller.update(2000.0), 400.0); // rate limited
+        assert_eq!(controller.update(2000.0), 800.0);
+        assert_eq!(controller.update(1000.0), 900.0); // half of the difference
+        assert_eq!(controller.update(950.0), 900.0); // within the deadband
+
+        let mut controller = PowerController::new(Some(ControllerSettings {
+            smoothing: 0.5,
+            ..ControllerSettings::default()
+        }));
+        assert_eq!(controller.update(1000.0), 1000.0);
+        assert_eq!(controller.update(0.0), 500.0);
+        assert_eq!(controller.update(0.0), 250.0);
+    }
+
+    #[test]
+    fn test_power_controller_integral_does_not_overshoot() {
+        let settings = ControllerSettings {
+            kp: 0.5,
+            ki: 0.2,
+            ..ControllerSettings::default()
+        };
+        for target in [1000.0, -1000.0] {
+            let mut controller = PowerController::new(Some(settings.clone()));
+            let mut last = 0.0_f32;
+            for _ in 0..10 {
+                let output = controller.update(target);
+                // monotone towards the target, never past it
+                assert!(output.abs() >= last.abs() && output.abs() <= 1000.0);
+                last = output;
+            }
+            assert_eq!(last, target);
+        }
+
+        // and back down after the computed power drops
+        let mut controller = PowerController::new(Some(settings));
+        for _ in 0..5 {
+            controller.update(1000.0);
+        }
+        let mut last = 1000.0_f32;
+        for _ in 0..10 {
+            let output = controller.update(500.0);
+            assert!(output <= last && output >= 500.0);
+            last = output;
+        }
+        assert_eq!(last, 500.0);
+    }
+}
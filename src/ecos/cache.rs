@@ -0,0 +1,91 @@
+// ecos/cache.rs
+use rocket::tokio::sync::Mutex;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// A response with the time since it was fetched from ECOS
+#[derive(Debug, Clone)]
+pub struct Cached<T> {
+    pub value: T,
+    pub age: Duration,
+}
+
+impl<T> Cached<T> {
+    /// A response just fetched from ECOS
+    pub fn fresh(value: T) -> Self {
+        Cached {
+            value,
+            age: Duration::ZERO,
+        }
+    }
+}
+
+/// Responses kept for a fixed time, keyed by device id. A zero TTL disables caching.
+pub struct TtlCache<V> {
+    ttl: Duration,
+    entries: Mutex<HashMap<String, (Instant, V)>>,
+}
+
+impl<V: Clone> TtlCache<V> {
+    pub fn new(ttl: Duration) -> Self {
+        TtlCache {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub async fn get(&self, key: &str) -> Option<Cached<V>> {
+        let entries = self.entries.lock().await;
+        let (fetched_at, value) = entries.get(key)?;
+        let age = fetched_at.elapsed();
+        (age < self.ttl).then(|| Cached {
+            value: value.clone(),
+            age,
+        })
+    }
+
+    pub async fn insert(&self, key: &str, value: V) {
+        if self.ttl.is_zero() {
+            return;
+        }
+        self.entries
+            .lock()
+            .await
+            .insert(key.to_string(), (Instant::now(), value));
+    }
+
+    pub async fn invalidate(&self, key: &str) {
+        self.entries.lock().await.remove(key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::tokio;
+
+    #[tokio::test]
+    async fn test_ttl_cache() {
+        let cache = TtlCache::new(Duration::from_millis(50));
+        assert!(cache.get("a").await.is_none());
+
+        cache.insert("a", 1).await;
+        let cached = cache.get("a").await.unwrap();
+        assert_eq!(cached.value, 1);
+        assert!(cached.age < Duration::from_millis(50));
+
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert!(cache.get("a").await.is_none());
+
+        cache.insert("a", 2).await;
+        cache.invalidate("a").await;
+        assert!(cache.get("a").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_zero_ttl_disables_cache() {
+        let cache = TtlCache::new(Duration::ZERO);
+        cache.insert("a", 1).await;
+        assert!(cache.get("a").await.is_none());
+    }
+}
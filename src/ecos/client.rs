@@ -2,11 +2,15 @@
 use reqwest::{Client, Response, StatusCode};
 use rocket::tokio::sync::Mutex;
 use std::sync::Arc;
+use std::time::Duration;
 
+use crate::config::CacheConfig;
+use crate::ecos::cache::{Cached, TtlCache};
 use crate::ecos::data_models::{
-    ChargeModeSettingsRequest, ChargeModeSettingsResponse, Claims, DevicesResponse, LoginRequest,
-    LoginResponse, RunDataRequest, RunDataResponse,
+    ChargeModeSettingsRequest, ChargeModeSettingsResponse, Claims, DevicesResponse, EcosResponse,
+    LoginRequest, LoginResponse, RunDataRequest, RunDataResponse,
 };
+use crate::metrics::metrics;
 
 pub struct EcosClient {
     user: String,
@@ -15,6 +19,9 @@ pub struct EcosClient {
     base_url: String,
     client: Client,
     retries: u8,
+    devices_cache: TtlCache<DevicesResponse>,
+    run_data_cache: TtlCache<RunDataResponse>,
+    charge_mode_settings_cache: TtlCache<ChargeModeSettingsResponse>,
 }
 
 #[macro_export]
@@ -47,6 +54,7 @@ macro_rules! make_query_with_time_device_info {
 #[allow(dead_code)]
 impl EcosClient {
     pub fn new(user: String, password: String, base_url: String) -> Self {
+        let cache = CacheConfig::default();
         EcosClient {
             user,
             password,
@@ -54,6 +62,23 @@ impl EcosClient {
             base_url,
             client: Client::new(),
             retries: 3,
+            devices_cache: TtlCache::new(Duration::from_secs(cache.devices)),
+            run_data_cache: TtlCache::new(Duration::from_secs(cache.run_data)),
+            charge_mode_settings_cache: TtlCache::new(Duration::from_secs(
+                cache.charge_mode_settings,
+            )),
+        }
+    }
+
+    /// Set the time responses of the read endpoints are reused for
+    pub fn with_cache(self, config: &CacheConfig) -> Self {
+        EcosClient {
+            devices_cache: TtlCache::new(Duration::from_secs(config.devices)),
+            run_data_cache: TtlCache::new(Duration::from_secs(config.run_data)),
+            charge_mode_settings_cache: TtlCache::new(Duration::from_secs(
+                config.charge_mode_settings,
+            )),
+            ..self
         }
     }
 
@@ -65,6 +90,7 @@ impl EcosClient {
     }
 
     pub async fn login(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        metrics().ecos_logins.inc();
         let login_request = make_struct_with_time_device_info!(
             LoginRequest,
             email: self.user.clone(),
@@ -79,10 +105,10 @@ impl EcosClient {
             .await?;
 
         if !res.status().is_success() {
-            return Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                format!("Failed to login (status: {})", res.status()),
-            )));
+            return Err(Box::new(std::io::Error::other(format!(
+                "Failed to login (status: {})",
+                res.status()
+            ))));
         }
 
         let login_response: LoginResponse = res.json().await?;
@@ -92,14 +118,35 @@ impl EcosClient {
             *token = Some(data.accessToken);
             Ok(())
         } else {
-            Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::Other,
+            Err(Box::new(std::io::Error::other(
                 "Failed to login (success=false)",
             )))
         }
     }
 
+    /// Send a request, logging in first if needed, and record its metrics under `endpoint`
     async fn retry_request<F>(
+        &self,
+        endpoint: &str,
+        req_builder_func: F,
+    ) -> Result<Response, Box<dyn std::error::Error + Send + Sync>>
+    where
+        F: Fn() -> reqwest::RequestBuilder,
+    {
+        metrics().ecos_requests.with_label_values(&[endpoint]).inc();
+        let timer = metrics()
+            .ecos_latency
+            .with_label_values(&[endpoint])
+            .start_timer();
+        let res = self.send_with_retries(req_builder_func).await;
+        timer.observe_duration();
+        if !res.as_ref().is_ok_and(|r| r.status().is_success()) {
+            metrics().ecos_errors.with_label_values(&[endpoint]).inc();
+        }
+        res
+    }
+
+    async fn send_with_retries<F>(
         &self,
         req_builder_func: F,
     ) -> Result<Response, Box<dyn std::error::Error + Send + Sync>>
@@ -142,8 +189,22 @@ impl EcosClient {
     pub async fn get_devices(
         &self,
     ) -> Result<DevicesResponse, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(self.get_devices_cached().await?.value)
+    }
+
+    pub async fn get_devices_cached(
+        &self,
+    ) -> Result<Cached<DevicesResponse>, Box<dyn std::error::Error + Send + Sync>> {
+        if let Some(cached) = self.devices_cache.get("").await {
+            metrics()
+                .ecos_cache_hits
+                .with_label_values(&["devices"])
+                .inc();
+            return Ok(cached);
+        }
+
         let res = self
-            .retry_request(|| {
+            .retry_request("devices", || {
                 self.client
                     .get(format!("{}/client/home/device/list", self.base_url))
                     .query(make_query_with_time_device_info!())
@@ -151,21 +212,39 @@ impl EcosClient {
             .await?;
 
         let devices_response: DevicesResponse = res.json().await?;
+        self.devices_cache
+            .insert("", devices_response.clone())
+            .await;
 
-        Ok(devices_response)
+        Ok(Cached::fresh(devices_response))
     }
 
     pub async fn get_run_data(
         &self,
         device_id: String,
     ) -> Result<RunDataResponse, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(self.get_run_data_cached(device_id).await?.value)
+    }
+
+    pub async fn get_run_data_cached(
+        &self,
+        device_id: String,
+    ) -> Result<Cached<RunDataResponse>, Box<dyn std::error::Error + Send + Sync>> {
+        if let Some(cached) = self.run_data_cache.get(&device_id).await {
+            metrics()
+                .ecos_cache_hits
+                .with_label_values(&["run_data"])
+                .inc();
+            return Ok(cached);
+        }
+
         let run_data_request = make_struct_with_time_device_info!(
             RunDataRequest,
-            deviceId: device_id
+            deviceId: device_id.clone()
         );
 
         let res = self
-            .retry_request(|| {
+            .retry_request("run_data", || {
                 self.client
                     .post(format!("{}/client/home/now/device/runData", self.base_url))
                     .json(&run_data_request)
@@ -173,16 +252,45 @@ impl EcosClient {
             .await?;
 
         let run_data_response: RunDataResponse = res.json().await?;
+        metrics().observe_run_data(&run_data_response.data);
+        self.run_data_cache
+            .insert(&device_id, run_data_response.clone())
+            .await;
 
-        Ok(run_data_response)
+        Ok(Cached::fresh(run_data_response))
     }
 
     pub async fn get_charge_mode_settings(
         &self,
         device_id: &str,
     ) -> Result<ChargeModeSettingsResponse, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(self.get_charge_mode_settings_cached(device_id).await?.value)
+    }
+
+    /// The settings as they are on the device now, for changes that write them back.
+    /// The response refreshes the cache.
+    pub async fn get_charge_mode_settings_fresh(
+        &self,
+        device_id: &str,
+    ) -> Result<ChargeModeSettingsResponse, Box<dyn std::error::Error + Send + Sync>> {
+        self.charge_mode_settings_cache.invalidate(device_id).await;
+        self.get_charge_mode_settings(device_id).await
+    }
+
+    pub async fn get_charge_mode_settings_cached(
+        &self,
+        device_id: &str,
+    ) -> Result<Cached<ChargeModeSettingsResponse>, Box<dyn std::error::Error + Send + Sync>> {
+        if let Some(cached) = self.charge_mode_settings_cache.get(device_id).await {
+            metrics()
+                .ecos_cache_hits
+                .with_label_values(&["get_charge_mode_settings"])
+                .inc();
+            return Ok(cached);
+        }
+
         let res = self
-            .retry_request(|| {
+            .retry_request("get_charge_mode_settings", || {
                 self.client
                     .get(format!("{}/client/customize/info", self.base_url))
                     .query(make_query_with_time_device_info!("deviceId": device_id.to_string()))
@@ -190,29 +298,44 @@ impl EcosClient {
             .await?;
 
         let charge_mode_settings_response: ChargeModeSettingsResponse = res.json().await?;
+        self.charge_mode_settings_cache
+            .insert(device_id, charge_mode_settings_response.clone())
+            .await;
 
-        Ok(charge_mode_settings_response)
+        Ok(Cached::fresh(charge_mode_settings_response))
     }
 
     pub async fn post_charge_mode_settings(
         &self,
         charge_mode_settings_request: ChargeModeSettingsRequest,
-    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    ) -> Result<EcosResponse, Box<dyn std::error::Error + Send + Sync>> {
         let res = self
-            .retry_request(|| {
+            .retry_request("post_charge_mode_settings", || {
                 self.client
                     .post(format!("{}/client/customize/info", self.base_url))
                     .json(&charge_mode_settings_request)
             })
             .await?;
 
-        if res.status().is_success() {
-            Ok(())
-        } else {
-            Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::Other,
+        // the cached settings are stale even if the post failed part way
+        self.charge_mode_settings_cache
+            .invalidate(&charge_mode_settings_request.deviceId)
+            .await;
+
+        if !res.status().is_success() {
+            return Err(Box::new(std::io::Error::other(
                 "Failed to post charge mode settings",
-            )))
+            )));
+        }
+
+        let response: EcosResponse = res.json().await?;
+        if response.success {
+            Ok(response)
+        } else {
+            Err(Box::new(std::io::Error::other(format!(
+                "Failed to post charge mode settings (success=false, code: {}, message: {})",
+                response.code, response.message
+            ))))
         }
     }
 }
@@ -1,3 +1,4 @@
 // ecos/mod.rs
+pub mod cache;
 pub mod client;
 pub mod data_models;
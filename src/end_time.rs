@@ -0,0 +1,86 @@
+use chrono::{DateTime, Duration, Local, NaiveDateTime, NaiveTime, TimeZone};
+use rocket::serde::{de, Deserialize, Deserializer};
+
+/// Parse the end of a timed mode: an RFC 3339 timestamp in any timezone, a local date and time
+/// (`YYYY-MM-DDTHH:MM[:SS]`) or a local time of day (`HH:MM[:SS]`), meaning its next occurrence after `now`.
+/// A local time that falls in a DST gap is moved past the gap; an ambiguous one takes the earlier instant.
+pub fn parse_end_time(value: &str, now: DateTime<Local>) -> Option<DateTime<Local>> {
+    let value = value.trim();
+    if let Ok(ts) = DateTime::parse_from_rfc3339(value) {
+        return Some(ts.with_timezone(&Local));
+    }
+    for format in [
+        "%Y-%m-%dT%H:%M:%S",
+        "%Y-%m-%dT%H:%M",
+        "%Y-%m-%d %H:%M:%S",
+        "%Y-%m-%d %H:%M",
+    ] {
+        if let Ok(naive) = NaiveDateTime::parse_from_str(value, format) {
+            return local(naive);
+        }
+    }
+    let time = NaiveTime::parse_from_str(value, "%H:%M:%S")
+        .or_else(|_| NaiveTime::parse_from_str(value, "%H:%M"))
+        .ok()?;
+    let today = local(now.date_naive().and_time(time))?;
+    if today > now {
+        Some(today)
+    } else {
+        local((now.date_naive() + Duration::days(1)).and_time(time))
+    }
+}
+
+fn local(naive: NaiveDateTime) -> Option<DateTime<Local>> {
+    Local.from_local_datetime(&naive).earliest().or_else(|| {
+        Local
+            .from_local_datetime(&(naive + Duration::hours(1)))
+            .earliest()
+    })
+}
+
+/// Deserialize an optional end time with [`parse_end_time`], relative to the current time
+pub fn deserialize_end_time<'de, D>(deserializer: D) -> Result<Option<DateTime<Local>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let Some(value) = Option::<String>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+    parse_end_time(&value, Local::now())
+        .map(Some)
+        .ok_or_else(|| {
+            de::Error::custom(format!(
+                "invalid until {:?}, expected an RFC 3339 timestamp, YYYY-MM-DDTHH:MM or HH:MM",
+                value
+            ))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_end_time() {
+        let now = Local.with_ymd_and_hms(2025, 1, 10, 12, 0, 0).unwrap();
+
+        let later = parse_end_time("17:30", now).unwrap();
+        assert_eq!(
+            later,
+            Local.with_ymd_and_hms(2025, 1, 10, 17, 30, 0).unwrap()
+        );
+        let tomorrow = parse_end_time("08:15", now).unwrap();
+        assert_eq!(
+            tomorrow,
+            Local.with_ymd_and_hms(2025, 1, 11, 8, 15, 0).unwrap()
+        );
+
+        let local = parse_end_time("2025-01-12T06:00", now).unwrap();
+        assert_eq!(local, Local.with_ymd_and_hms(2025, 1, 12, 6, 0, 0).unwrap());
+
+        let utc = parse_end_time("2025-01-10T12:30:00Z", now).unwrap();
+        assert_eq!(utc.timestamp(), 1736512200);
+
+        assert!(parse_end_time("tomorrow", now).is_none());
+    }
+}
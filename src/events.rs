@@ -0,0 +1,41 @@
+use crate::ecos::data_models::RunData;
+use crate::state::ChargeMode;
+use chrono::{DateTime, Local};
+use rocket::serde::{Deserialize, Serialize};
+
+/// Live events pushed to `/events` subscribers
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "rocket::serde", tag = "type", rename_all = "kebab-case")]
+pub enum ControllerEvent {
+    /// A run data snapshot from the poller
+    RunData {
+        timestamp: DateTime<Local>,
+        data: RunData,
+    },
+    /// The charge power computed by the control loop (negative when discharging)
+    ChargePower {
+        timestamp: DateTime<Local>,
+        power: f32,
+    },
+    ModeChange {
+        timestamp: DateTime<Local>,
+        mode: ChargeMode,
+    },
+    /// A timed mode ran out and the controller is reverting to the default mode
+    ModeExpired {
+        timestamp: DateTime<Local>,
+        mode: ChargeMode,
+    },
+}
+
+impl ControllerEvent {
+    /// The SSE event name
+    pub fn name(&self) -> &'static str {
+        match self {
+            ControllerEvent::RunData { .. } => "run-data",
+            ControllerEvent::ChargePower { .. } => "charge-power",
+            ControllerEvent::ModeChange { .. } => "mode-change",
+            ControllerEvent::ModeExpired { .. } => "mode-expired",
+        }
+    }
+}
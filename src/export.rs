@@ -0,0 +1,339 @@
+use crate::history::{ModeChange, RunDataSample};
+use crate::routes::params::TimeParam;
+use crate::storage::{JsonLines, Timestamped};
+use chrono::{DateTime, Local};
+use parquet::data_type::{BoolType, ByteArray, ByteArrayType, FloatType, Int32Type, Int64Type};
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::schema::parser::parse_message_type;
+use rocket::serde::de::DeserializeOwned;
+use rocket::serde::json::serde_json;
+use rocket::serde::Serialize;
+use rocket::FromFormField;
+use std::path::Path;
+use std::sync::Arc;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromFormField)]
+pub enum ExportFormat {
+    Csv,
+    Parquet,
+}
+
+impl ExportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Parquet => "parquet",
+        }
+    }
+}
+
+pub enum Column {
+    Timestamp(Vec<DateTime<Local>>),
+    Float(Vec<f32>),
+    Int(Vec<i32>),
+    Bool(Vec<bool>),
+    Text(Vec<String>),
+}
+
+impl Column {
+    fn parquet_type(&self) -> &'static str {
+        match self {
+            Column::Timestamp(_) => "INT64",
+            Column::Float(_) => "FLOAT",
+            Column::Int(_) => "INT32",
+            Column::Bool(_) => "BOOLEAN",
+            Column::Text(_) => "BYTE_ARRAY",
+        }
+    }
+
+    fn parquet_annotation(&self) -> &'static str {
+        match self {
+            Column::Timestamp(_) => " (TIMESTAMP(MILLIS,true))",
+            Column::Text(_) => " (UTF8)",
+            _ => "",
+        }
+    }
+
+    fn csv_value(&self, row: usize) -> String {
+        match self {
+            Column::Timestamp(v) => v[row].to_rfc3339(),
+            Column::Float(v) => v[row].to_string(),
+            Column::Int(v) => v[row].to_string(),
+            Column::Bool(v) => v[row].to_string(),
+            Column::Text(v) => v[row].clone(),
+        }
+    }
+}
+
+/// A columnar view of exported records.
+/// The column names are part of the export format and should not be renamed.
+pub struct Table {
+    pub name: &'static str,
+    pub rows: usize,
+    pub columns: Vec<(&'static str, Column)>,
+}
+
+impl Table {
+    pub fn run_data(samples: &[RunDataSample]) -> Self {
+        let col = |f: fn(&RunDataSample) -> f32| Column::Float(samples.iter().map(f).collect());
+        Table {
+            name: "run_data",
+            rows: samples.len(),
+            columns: vec![
+                (
+                    "timestamp",
+                    Column::Timestamp(samples.iter().map(|s| s.timestamp).collect()),
+                ),
+                ("batterySoc", col(|s| s.data.batterySoc)),
+                ("batteryPower", col(|s| s.data.batteryPower)),
+                ("epsPower", col(|s| s.data.epsPower)),
+                ("gridPower", col(|s| s.data.gridPower)),
+                ("homePower", col(|s| s.data.homePower)),
+                ("meterPower", col(|s| s.data.meterPower)),
+                ("solarPower", col(|s| s.data.solarPower)),
+                (
+                    "sysRunMode",
+                    Column::Int(samples.iter().map(|s| s.data.sysRunMode).collect()),
+                ),
+                (
+                    "isExistSolar",
+                    Column::Bool(samples.iter().map(|s| s.data.isExistSolar).collect()),
+                ),
+                (
+                    "sysPowerConfig",
+                    Column::Int(samples.iter().map(|s| s.data.sysPowerConfig).collect()),
+                ),
+            ],
+        }
+    }
+
+    /// The charge mode timeline, with the mode name and its parameters as a JSON object
+    pub fn mode_changes(changes: &[ModeChange]) -> Self {
+        let (modes, parameters) = changes
+            .iter()
+            .map(|c| {
+                let mut value = serde_json::to_value(&c.mode).unwrap_or_default();
+                let mode = value
+                    .as_object_mut()
+                    .and_then(|o| o.remove("mode"))
+                    .and_then(|m| m.as_str().map(str::to_string))
+                    .unwrap_or_default();
+                (mode, value.to_string())
+            })
+            .unzip();
+        Table {
+            name: "charge_modes",
+            rows: changes.len(),
+            columns: vec![
+                (
+                    "timestamp",
+                    Column::Timestamp(changes.iter().map(|c| c.timestamp).collect()),
+                ),
+                ("mode", Column::Text(modes)),
+                ("parameters", Column::Text(parameters)),
+            ],
+        }
+    }
+
+    pub fn write(&self, format: ExportFormat) -> Result<Vec<u8>, BoxError> {
+        match format {
+            ExportFormat::Csv => self.to_csv(),
+            ExportFormat::Parquet => self.to_parquet(),
+        }
+    }
+
+    pub fn to_csv(&self) -> Result<Vec<u8>, BoxError> {
+        let mut writer = csv::Writer::from_writer(vec![]);
+        writer.write_record(self.columns.iter().map(|(name, _)| *name))?;
+        for row in 0..self.rows {
+            writer.write_record(self.columns.iter().map(|(_, c)| c.csv_value(row)))?;
+        }
+        Ok(writer.into_inner()?)
+    }
+
+    pub fn to_parquet(&self) -> Result<Vec<u8>, BoxError> {
+        let fields = self
+            .columns
+            .iter()
+            .map(|(name, c)| {
+                format!(
+                    "REQUIRED {} {}{};",
+                    c.parquet_type(),
+                    name,
+                    c.parquet_annotation()
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+        let schema = parse_message_type(&format!("message {} {{ {} }}", self.name, fields))?;
+
+        let mut buffer = vec![];
+        let mut writer = SerializedFileWriter::new(
+            &mut buffer,
+            Arc::new(schema),
+            Arc::new(WriterProperties::builder().build()),
+        )?;
+        let mut row_group = writer.next_row_group()?;
+        for (_, column) in &self.columns {
+            let mut col = row_group
+                .next_column()?
+                .ok_or("parquet schema has fewer columns than the table")?;
+            match column {
+                Column::Timestamp(v) => {
+                    let millis: Vec<i64> = v.iter().map(|t| t.timestamp_millis()).collect();
+                    col.typed::<Int64Type>().write_batch(&millis, None, None)?;
+                }
+                Column::Float(v) => {
+                    col.typed::<FloatType>().write_batch(v, None, None)?;
+                }
+                Column::Int(v) => {
+                    col.typed::<Int32Type>().write_batch(v, None, None)?;
+                }
+                Column::Bool(v) => {
+                    col.typed::<BoolType>().write_batch(v, None, None)?;
+                }
+                Column::Text(v) => {
+                    let bytes: Vec<ByteArray> = v.iter().map(|s| s.as_str().into()).collect();
+                    col.typed::<ByteArrayType>()
+                        .write_batch(&bytes, None, None)?;
+                }
+            }
+            col.close()?;
+        }
+        row_group.close()?;
+        writer.close()?;
+        Ok(buffer)
+    }
+}
+
+async fn read_store<T: Serialize + DeserializeOwned + Timestamped>(
+    path: &Path,
+    from: Option<DateTime<Local>>,
+    to: Option<DateTime<Local>>,
+) -> Result<Vec<T>, BoxError> {
+    let store = JsonLines::<T>::open_monthly(path);
+    let from = from.unwrap_or(DateTime::<Local>::MIN_UTC.into());
+    let to = to.unwrap_or_else(Local::now);
+    store.read_range(from, to).await
+}
+
+/// Run the `export` subcommand:
+/// `export <run-data|charge-modes> [--format csv|parquet] [--from T] [--to T] [--output FILE]`.
+/// Without `--from`/`--to` everything recorded is exported; without `--output` it is written to stdout.
+pub async fn run_cli(args: &[String], data_dir: &Path) -> Result<(), BoxError> {
+    let usage = "usage: export <run-data|charge-modes> [--format csv|parquet] [--from T] [--to T] [--output FILE]";
+    let table = args.first().ok_or(usage)?;
+    let mut format = ExportFormat::Csv;
+    let (mut from, mut to, mut output) = (None, None, None);
+
+    let mut rest = args[1..].iter();
+    while let Some(flag) = rest.next() {
+        let value = rest.next().ok_or(usage)?;
+        let time = || TimeParam::parse(value).ok_or(format!("invalid time: {}", value));
+        match flag.as_str() {
+            "--format" => {
+                format = match value.as_str() {
+                    "csv" => ExportFormat::Csv,
+                    "parquet" => ExportFormat::Parquet,
+                    _ => return Err(usage.into()),
+                }
+            }
+            "--from" => from = Some(time()?),
+            "--to" => to = Some(time()?),
+            "--output" => output = Some(value.clone()),
+            _ => return Err(usage.into()),
+        }
+    }
+
+    let table = match table.as_str() {
+        "run-data" => {
+            let samples = read_store(&data_dir.join("history.jsonl"), from, to).await?;
+            Table::run_data(&samples)
+        }
+        "charge-modes" => {
+            let changes = read_store(&data_dir.join("charge-modes.jsonl"), from, to).await?;
+            Table::mode_changes(&changes)
+        }
+        _ => return Err(usage.into()),
+    };
+    let bytes = table.write(format)?;
+    match output {
+        Some(path) => std::fs::write(path, bytes)?,
+        None => std::io::Write::write_all(&mut std::io::stdout(), &bytes)?,
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ecos::data_models::RunData;
+    use crate::state::ChargeMode;
+    use chrono::TimeZone;
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+
+    fn mode_changes() -> Vec<ModeChange> {
+        vec![ModeChange {
+            timestamp: Local.with_ymd_and_hms(2025, 1, 1, 8, 0, 0).unwrap(),
+            mode: ChargeMode::SelfSufficient { battery_level: 10 },
+        }]
+    }
+
+    #[test]
+    fn test_run_data_has_a_column_per_field() {
+        let data: RunData = serde_json::from_value(serde_json::json!({
+            "batterySoc": 50.0, "batteryPower": 0.0, "epsPower": 0.0, "gridPower": 0.0,
+            "homePower": 500.0, "meterPower": 0.0, "solarPower": 500.0, "sysRunMode": 1,
+            "isExistSolar": true, "sysPowerConfig": 3
+        }))
+        .unwrap();
+        let value = serde_json::to_value(&data).unwrap();
+        let mut fields: Vec<&str> = value
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(String::as_str)
+            // the serde tag of the model, not a field
+            .filter(|key| *key != "ecos")
+            .collect();
+        let table = Table::run_data(&[RunDataSample {
+            timestamp: Local::now(),
+            data,
+        }]);
+        let mut columns: Vec<&str> = table.columns.iter().map(|(name, _)| *name).collect();
+        assert_eq!(columns.remove(0), "timestamp");
+        // a new RunData field needs a column, or the export drops it
+        columns.sort_unstable();
+        fields.sort_unstable();
+        assert_eq!(columns, fields);
+    }
+
+    #[test]
+    fn test_mode_changes_csv() {
+        let csv = Table::mode_changes(&mode_changes()).to_csv().unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let mut lines = csv.lines();
+        assert_eq!(lines.next(), Some("timestamp,mode,parameters"));
+        let row = lines.next().unwrap();
+        assert!(row.contains(",self-sufficient,"));
+        assert!(row.contains(r#""{""battery_level"":10}""#));
+    }
+
+    #[test]
+    fn test_mode_changes_parquet() {
+        let bytes = Table::mode_changes(&mode_changes()).to_parquet().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("modes.parquet");
+        std::fs::write(&path, bytes).unwrap();
+
+        let reader = SerializedFileReader::new(std::fs::File::open(&path).unwrap()).unwrap();
+        let metadata = reader.metadata();
+        assert_eq!(metadata.file_metadata().num_rows(), 1);
+        let fields = metadata.file_metadata().schema().get_fields();
+        let names: Vec<&str> = fields.iter().map(|f| f.name()).collect();
+        assert_eq!(names, vec!["timestamp", "mode", "parameters"]);
+    }
+}
@@ -0,0 +1,246 @@
+#![allow(non_snake_case)]
+use crate::ecos::data_models::RunData;
+use crate::state::{grid_power, ChargeMode};
+use crate::storage::Timestamped;
+use chrono::{DateTime, Datelike, Duration, Local, TimeZone, Timelike};
+use rocket::serde::{Deserialize, Serialize};
+use rocket::FromFormField;
+
+/// Samples further apart than this are treated as a gap in the recording
+/// and are not integrated over.
+pub const MAX_SAMPLE_GAP: Duration = Duration::minutes(15);
+
+/// A `RunData` snapshot as recorded by the poller
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct RunDataSample {
+    pub timestamp: DateTime<Local>,
+    pub data: RunData,
+}
+
+impl RunDataSample {
+    pub fn now(data: RunData) -> Self {
+        RunDataSample {
+            timestamp: Local::now(),
+            data,
+        }
+    }
+}
+
+impl Timestamped for RunDataSample {
+    fn timestamp(&self) -> DateTime<Local> {
+        self.timestamp
+    }
+}
+
+/// An entry of the charge mode timeline, recorded whenever the mode changes
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct ModeChange {
+    pub timestamp: DateTime<Local>,
+    pub mode: ChargeMode,
+}
+
+impl Timestamped for ModeChange {
+    fn timestamp(&self) -> DateTime<Local> {
+        self.timestamp
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromFormField)]
+pub enum Resolution {
+    Raw,
+    Minute,
+    Hour,
+    Day,
+    Month,
+}
+
+impl Resolution {
+    /// The start of the bucket containing `ts`, in local time
+    pub fn bucket_start(&self, ts: DateTime<Local>) -> DateTime<Local> {
+        let naive = ts.naive_local();
+        let truncated = match self {
+            Resolution::Raw => return ts,
+            Resolution::Minute => naive.with_second(0).and_then(|t| t.with_nanosecond(0)),
+            Resolution::Hour => naive.date().and_hms_opt(naive.hour(), 0, 0),
+            Resolution::Day => naive.date().and_hms_opt(0, 0, 0),
+            Resolution::Month => naive
+                .date()
+                .with_day(1)
+                .and_then(|d| d.and_hms_opt(0, 0, 0)),
+        };
+        truncated
+            .and_then(|t| Local.from_local_datetime(&t).earliest())
+            .unwrap_or(ts)
+    }
+}
+
+/// Average power readings over one bucket, in watts (SoC in percent)
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "rocket::serde")]
+pub struct PowerPoint {
+    pub timestamp: DateTime<Local>,
+    pub samples: usize,
+    pub batterySoc: f32,
+    pub batteryPower: f32,
+    pub epsPower: f32,
+    pub gridPower: f32,
+    pub homePower: f32,
+    pub meterPower: f32,
+    pub solarPower: f32,
+}
+
+/// Energy totals over one bucket, in kWh.
+/// `gridPower` is positive when importing and `batteryPower` is positive when charging.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(crate = "rocket::serde")]
+pub struct EnergyTotals {
+    pub timestamp: DateTime<Local>,
+    pub pvGeneration: f64,
+    pub gridImport: f64,
+    pub gridExport: f64,
+    pub batteryCharge: f64,
+    pub batteryDischarge: f64,
+    pub homeConsumption: f64,
+}
+
+/// Group samples (sorted by time) into buckets and average the power readings
+pub fn power_series(samples: &[RunDataSample], resolution: Resolution) -> Vec<PowerPoint> {
+    let mut points: Vec<PowerPoint> = vec![];
+    for sample in samples {
+        let bucket = resolution.bucket_start(sample.timestamp);
+        let d = &sample.data;
+        match points.last_mut() {
+            Some(p) if p.timestamp == bucket => {
+                let n = p.samples as f32;
+                let avg = |acc: f32, v: f32| (acc * n + v) / (n + 1.0);
+                p.batterySoc = avg(p.batterySoc, d.batterySoc);
+                p.batteryPower = avg(p.batteryPower, d.batteryPower);
+                p.epsPower = avg(p.epsPower, d.epsPower);
+                p.gridPower = avg(p.gridPower, d.gridPower);
+                p.homePower = avg(p.homePower, d.homePower);
+                p.meterPower = avg(p.meterPower, d.meterPower);
+                p.solarPower = avg(p.solarPower, d.solarPower);
+                p.samples += 1;
+            }
+            _ => points.push(PowerPoint {
+                timestamp: bucket,
+                samples: 1,
+                batterySoc: d.batterySoc,
+                batteryPower: d.batteryPower,
+                epsPower: d.epsPower,
+                gridPower: d.gridPower,
+                homePower: d.homePower,
+                meterPower: d.meterPower,
+                solarPower: d.solarPower,
+            }),
+        }
+    }
+    points
+}
+
+/// Integrate the power readings of consecutive samples (sorted by time) into energy per bucket.
+/// Each reading is held until the next sample; the interval is attributed to the bucket
+/// of the earlier sample.
+pub fn energy_totals(samples: &[RunDataSample], resolution: Resolution) -> Vec<EnergyTotals> {
+    let mut totals: Vec<EnergyTotals> = vec![];
+    for pair in samples.windows(2) {
+        let (a, b) = (&pair[0], &pair[1]);
+        let dt = b.timestamp - a.timestamp;
+        if dt <= Duration::zero() || dt > MAX_SAMPLE_GAP {
+            continue;
+        }
+        let hours = dt.num_milliseconds() as f64 / 3_600_000.0;
+        let kwh = |w: f32| w as f64 * hours / 1000.0;
+
+        let bucket = resolution.bucket_start(a.timestamp);
+        if totals.last().map(|t| t.timestamp) != Some(bucket) {
+            totals.push(EnergyTotals {
+                timestamp: bucket,
+                ..Default::default()
+            });
+        }
+        let t = totals.last_mut().unwrap();
+        let d = &a.data;
+        t.pvGeneration += kwh(d.solarPower.max(0.0));
+        let grid = grid_power(d);
+        t.gridImport += kwh(grid.max(0.0));
+        t.gridExport += kwh((-grid).max(0.0));
+        t.batteryCharge += kwh(d.batteryPower.max(0.0));
+        t.batteryDischarge += kwh((-d.batteryPower).max(0.0));
+        t.homeConsumption += kwh((d.homePower + d.epsPower).max(0.0));
+    }
+    totals
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(minute: u32, solar: f32, grid: f32, battery: f32) -> RunDataSample {
+        RunDataSample {
+            timestamp: Local.with_ymd_and_hms(2025, 1, 1, 10, minute, 0).unwrap(),
+            data: RunData {
+                batterySoc: 50.0,
+                batteryPower: battery,
+                epsPower: 0.0,
+                gridPower: grid,
+                homePower: 1000.0,
+                meterPower: 0.0,
+                solarPower: solar,
+                sysRunMode: 0,
+                isExistSolar: true,
+                sysPowerConfig: 0,
+            },
+        }
+    }
+
+    #[test]
+    fn test_power_series_averages_buckets() {
+        let samples = vec![sample(0, 1000.0, 0.0, 0.0), sample(30, 3000.0, 0.0, 0.0)];
+        let points = power_series(&samples, Resolution::Hour);
+        assert_eq!(points.len(), 1);
+        assert_eq!(points[0].samples, 2);
+        assert_eq!(points[0].solarPower, 2000.0);
+
+        let points = power_series(&samples, Resolution::Minute);
+        assert_eq!(points.len(), 2);
+    }
+
+    #[test]
+    fn test_energy_totals_integrates_and_splits_signs() {
+        let samples = vec![
+            sample(0, 2000.0, -1000.0, 500.0),
+            sample(6, 2000.0, 1000.0, -500.0),
+            sample(12, 0.0, 0.0, 0.0),
+            // gap longer than MAX_SAMPLE_GAP is ignored
+            sample(59, 0.0, 0.0, 0.0),
+        ];
+        let totals = energy_totals(&samples, Resolution::Hour);
+        assert_eq!(totals.len(), 1);
+        let t = &totals[0];
+        assert!((t.pvGeneration - 0.4).abs() < 1e-9);
+        assert!((t.gridExport - 0.1).abs() < 1e-9);
+        assert!((t.gridImport - 0.1).abs() < 1e-9);
+        assert!((t.batteryCharge - 0.05).abs() < 1e-9);
+        assert!((t.batteryDischarge - 0.05).abs() < 1e-9);
+        assert!((t.homeConsumption - 0.2).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_energy_totals_prefers_the_meter() {
+        let mut samples = vec![sample(0, 0.0, -1000.0, 0.0), sample(6, 0.0, 0.0, 0.0)];
+        samples[0].data.meterPower = 2000.0;
+        let totals = energy_totals(&samples, Resolution::Hour);
+        assert!((totals[0].gridImport - 0.2).abs() < 1e-9);
+        assert_eq!(totals[0].gridExport, 0.0);
+    }
+
+    #[test]
+    fn test_bucket_start_month() {
+        let ts = Local.with_ymd_and_hms(2025, 3, 17, 13, 45, 12).unwrap();
+        let start = Resolution::Month.bucket_start(ts);
+        assert_eq!(start, Local.with_ymd_and_hms(2025, 3, 1, 0, 0, 0).unwrap());
+    }
+}
@@ -0,0 +1,283 @@
+use crate::audit::Actor;
+use crate::config::MqttConfig;
+use crate::state::{AppState, ChargeMode};
+use crate::validation::{validate_mode, MAX_DURATION, MAX_SIDE_LOAD};
+use rocket::log::private::{info, warn};
+use rocket::serde::json::{json, serde_json, Value};
+use rocket::serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// The options of the `select` entity. Every mode is listed so that it can show the running one,
+/// but only those with parameters from the `number` entities can be selected, see [`Controls::to_mode`].
+pub const MODES: [&str; 8] = [
+    "conservative",
+    "active",
+    "self-sufficient",
+    "force-charge",
+    "force-export",
+    "export-limit",
+    "peak-shave",
+    "backup",
+];
+
+/// The values of the `number` entities, used when a mode is selected in Home Assistant
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "rocket::serde")]
+pub struct Controls {
+    pub battery_level: u8,
+    pub side_load: u32,
+    pub duration: u64,
+}
+
+impl Default for Controls {
+    fn default() -> Self {
+        Controls {
+            battery_level: 80,
+            side_load: 0,
+            duration: 60,
+        }
+    }
+}
+
+impl Controls {
+    /// Keep the controls in sync with a mode set from elsewhere
+    pub fn update_from_mode(&mut self, charge_mode: &ChargeMode) {
+        match *charge_mode {
+            ChargeMode::Conservative {
+                battery_level,
+                duration,
+                ..
+            } => {
+                self.battery_level = battery_level;
+                self.duration = duration;
+            }
+            ChargeMode::Active {
+                side_load,
+                duration,
+                ..
+            } => {
+                self.side_load = side_load;
+                self.duration = duration;
+            }
+            ChargeMode::SelfSufficient { battery_level } => {
+                self.battery_level = battery_level;
+            }
+            // not offered in Home Assistant
+            ChargeMode::ForceCharge { .. }
+            | ChargeMode::ForceExport { .. }
+            | ChargeMode::ExportLimit { .. }
+            | ChargeMode::PeakShave { .. }
+            | ChargeMode::Backup { .. } => {}
+        }
+    }
+
+    /// The mode selected by its name, with its parameters taken from the controls,
+    /// or None for the modes without controls
+    pub fn to_mode(&self, name: &str) -> Option<ChargeMode> {
+        match name {
+            "conservative" => Some(ChargeMode::Conservative {
+                battery_level: self.battery_level,
+                duration: self.duration,
+                until: None,
+            }),
+            "active" => Some(ChargeMode::Active {
+                side_load: self.side_load,
+                duration: self.duration,
+                until: None,
+                check_interval: None,
+                controller: None,
+            }),
+            "self-sufficient" => Some(ChargeMode::SelfSufficient {
+                battery_level: self.battery_level,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Set a control from its `number` entity, returning false for unknown controls or values
+    pub fn set(&mut self, control: &str, value: &str) -> bool {
+        // Home Assistant sends numbers as floats, e.g. "60.0"
+        let Ok(value) = value.trim().parse::<f64>() else {
+            return false;
+        };
+        if value < 0.0 {
+            return false;
+        }
+        match control {
+            "battery_level" if value <= 100.0 => self.battery_level = value as u8,
+            "side_load" => self.side_load = value as u32,
+            "duration" => self.duration = value as u64,
+            _ => return false,
+        }
+        true
+    }
+}
+
+/// The discovery config messages, as (topic, payload) pairs
+pub fn discovery_messages(config: &MqttConfig, device_id: &str) -> Vec<(String, Value)> {
+    let node_id = &config.node_id;
+    let device = json!({
+        "identifiers": [format!("{}_{}", node_id, device_id)],
+        "name": "eCactus",
+        "manufacturer": "Weiheng",
+        "model": "ECOS",
+    });
+    let entity = |component: &str, object_id: &str, mut payload: Value| {
+        payload["unique_id"] = json!(format!("{}_{}", node_id, object_id));
+        payload["object_id"] = json!(format!("{}_{}", node_id, object_id));
+        payload["device"] = device.clone();
+        (
+            format!(
+                "{}/{}/{}/{}/config",
+                config.discovery_prefix, component, node_id, object_id
+            ),
+            payload,
+        )
+    };
+
+    let mut messages = vec![entity(
+        "sensor",
+        "battery_soc",
+        json!({
+            "name": "Battery SoC",
+            "state_topic": config.run_data_topic,
+            "value_template": "{{ value_json.batterySoc }}",
+            "unit_of_measurement": "%",
+            "device_class": "battery",
+            "state_class": "measurement",
+        }),
+    )];
+    for (object_id, name, field) in [
+        ("solar_power", "PV power", "solarPower"),
+        ("grid_power", "Grid power", "gridPower"),
+        ("home_power", "Home power", "homePower"),
+        ("battery_power", "Battery power", "batteryPower"),
+    ] {
+        messages.push(entity(
+            "sensor",
+            object_id,
+            json!({
+                "name": name,
+                "state_topic": config.run_data_topic,
+                "value_template": format!("{{{{ value_json.{} }}}}", field),
+                "unit_of_measurement": "W",
+                "device_class": "power",
+                "state_class": "measurement",
+            }),
+        ));
+    }
+    messages.push(entity(
+        "select",
+        "charge_mode",
+        json!({
+            "name": "Charge mode",
+            "state_topic": config.mode_topic,
+            "value_template": "{{ value_json.mode }}",
+            "command_topic": format!("{}/mode/set", config.controls_topic),
+            "options": MODES,
+        }),
+    ));
+    for (object_id, name, min, max, unit) in [
+        ("battery_level", "Battery level", 0, 100, "%"),
+        ("side_load", "Side load", 0, MAX_SIDE_LOAD as u64, "W"),
+        ("duration", "Duration", 1, MAX_DURATION, "min"),
+    ] {
+        messages.push(entity(
+            "number",
+            object_id,
+            json!({
+                "name": name,
+                "state_topic": config.controls_topic,
+                "value_template": format!("{{{{ value_json.{} }}}}", object_id),
+                "command_topic": format!("{}/{}/set", config.controls_topic, object_id),
+                "min": min,
+                "max": max,
+                "unit_of_measurement": unit,
+                "mode": "box",
+            }),
+        ));
+    }
+    messages
+}
+
+pub async fn publish_discovery(state: &AppState) {
+    let Some(mqtt) = &state.mqtt else {
+        return;
+    };
+    for (topic, payload) in discovery_messages(&mqtt.config, &state.config().deviceId) {
+        mqtt.publish_json(&topic, &payload).await;
+    }
+    let controls = mqtt.controls.lock().await.clone();
+    mqtt.publish_json(&mqtt.config.controls_topic, &controls)
+        .await;
+}
+
+/// Handle a command from a `select` or `number` entity, published to `<controls_topic>/<control>/set`
+pub async fn handle_control(state: &Arc<AppState>, topic: &str, payload: &str) {
+    let Some(mqtt) = &state.mqtt else {
+        return;
+    };
+    let Some(control) = topic
+        .strip_prefix(&format!("{}/", mqtt.config.controls_topic))
+        .and_then(|t| t.strip_suffix("/set"))
+    else {
+        return;
+    };
+
+    let current_mode = state.current_mode.lock().await.clone();
+    let (name, controls) = {
+        let mut controls = mqtt.controls.lock().await;
+        if control == "mode" {
+            (payload.trim().to_string(), controls.clone())
+        } else if controls.set(control, payload) {
+            // a new value for the running mode is applied straight away
+            (current_mode.name().to_string(), controls.clone())
+        } else {
+            warn!("Ignoring invalid value {:?} for {}", payload, control);
+            return;
+        }
+    };
+    mqtt.publish_json(&mqtt.config.controls_topic, &controls)
+        .await;
+
+    let Some(charge_mode) = controls.to_mode(&name) else {
+        // a running mode without controls is not affected by them
+        if control != "mode" {
+            return;
+        }
+        if MODES.contains(&name.as_str()) {
+            warn!(
+                "Ignoring charge mode {:?}, which has no controls in Home Assistant",
+                name
+            );
+        } else {
+            warn!("Ignoring unknown charge mode {:?}", name);
+        }
+        return;
+    };
+    if control != "mode" && !uses_control(&charge_mode, control) {
+        return;
+    }
+    if let Err(e) = validate_mode(&charge_mode, &state.config()) {
+        warn!(
+            "Ignoring invalid charge mode {:?}: {:?}",
+            charge_mode, e.errors
+        );
+        return;
+    }
+    info!(target: "app", "Charge mode from Home Assistant: {:?}", charge_mode);
+    AppState::apply_mode(
+        state,
+        charge_mode.clone(),
+        Actor::Mqtt {
+            topic: topic.to_string(),
+        },
+    )
+    .await;
+    mqtt.publish_mode(&charge_mode).await;
+}
+
+fn uses_control(charge_mode: &ChargeMode, control: &str) -> bool {
+    let value = serde_json::to_value(charge_mode).unwrap_or_default();
+    value.get(control).is_some()
+}
@@ -1,4 +1,19 @@
+pub mod alerts;
+pub mod audit;
+pub mod auth;
 pub mod config;
+pub mod controller;
 pub mod ecos;
+pub mod end_time;
+pub mod events;
+pub mod export;
+pub mod history;
+pub mod home_assistant;
+pub mod metrics;
+pub mod mqtt;
+pub mod poller;
 pub mod routes;
+pub mod snapshots;
 pub mod state;
+pub mod storage;
+pub mod validation;
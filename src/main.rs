@@ -1,41 +1,102 @@
 extern crate rocket;
 
-mod config;
-mod ecos;
-mod routes;
-mod state;
-
-use crate::config::{read_config, Config};
-use crate::state::{AppState, ChargeMode};
-use rocket::tokio::sync::Mutex;
+use ecactus_controller::config::{read_config, Config};
+use ecactus_controller::ecos::client::EcosClient;
+use ecactus_controller::mqtt::Mqtt;
+use ecactus_controller::state::AppState;
+use ecactus_controller::storage::JsonLines;
+use ecactus_controller::{alerts, export, mqtt, poller, routes};
+use rocket::tokio;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
 
 #[rocket::main]
+#[allow(clippy::result_large_err)]
 async fn main() -> Result<(), rocket::Error> {
     let config_path = std::env::var("APP_CONFIG").unwrap_or_else(|_| "config.toml".to_string());
     let config: Config = read_config(&config_path);
+    let data_dir = Path::new(&config.storage.data_dir);
 
-    let ecos_client = Arc::new(ecos::client::EcosClient::new(
-        config.ecos.user,
-        config.ecos.password,
-        config.ecos.base_url,
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("export") {
+        if let Err(e) = export::run_cli(&args[1..], data_dir).await {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    if config.auth.keys.is_empty() {
+        if !config.auth.open {
+            eprintln!(
+                "No [[auth.keys]] are configured. Add keys, or set `open = true` in [auth] \
+                 to allow anyone who can reach the API to change the charge mode."
+            );
+            std::process::exit(1);
+        }
+        eprintln!(
+            "Warning: no [[auth.keys]] are configured, the API is open to anyone who can reach it"
+        );
+    }
+
+    let ecos_client = Arc::new(
+        EcosClient::new(config.ecos.user, config.ecos.password, config.ecos.base_url)
+            .with_cache(&config.ecos.cache),
+    );
+    let (mqtt_client, mqtt_eventloop) = config.mqtt.map(Mqtt::new).unzip();
+    let app_state = Arc::new(AppState {
+        history: JsonLines::open_monthly(data_dir.join("history.jsonl")),
+        mode_history: JsonLines::open_monthly(data_dir.join("charge-modes.jsonl")),
+        audit: JsonLines::open_monthly(data_dir.join("audit.jsonl")),
+        snapshots: JsonLines::open(data_dir.join("settings-snapshots.jsonl")),
+        mqtt: mqtt_client,
+        auth: config.auth,
+        config_path: Some(PathBuf::from(&config_path)),
+        ..AppState::new(config.app, ecos_client)
+    });
+
+    if let Some(eventloop) = mqtt_eventloop {
+        tokio::spawn(mqtt::run(app_state.clone(), eventloop));
+    }
+    if let Some(alerts_config) = config.alerts {
+        tokio::spawn(alerts::run(app_state.clone(), alerts_config));
+    }
+    #[cfg(unix)]
+    tokio::spawn(reload_on_sighup(app_state.clone()));
+    tokio::spawn(poller::run(
+        app_state.clone(),
+        Duration::from_secs(config.poller.interval),
     ));
-    let app_state = AppState {
-        current_mode: Mutex::new(ChargeMode::SelfSufficient {
-            battery_level: config.app.minCapacity as u8,
-        }),
-        expiration: Mutex::new(None),
-        background_task: Mutex::new(None),
-        app_config: config.app,
-        ecos_client,
-    };
 
     rocket::build()
         .mount("/", routes::charge_mode::routes())
+        .mount("/", routes::history::routes())
+        .mount("/", routes::export::routes())
+        .mount("/", routes::audit::routes())
+        .mount("/", routes::metrics::routes())
+        .mount("/", routes::events::routes())
+        .mount("/", routes::openapi::routes())
+        .mount("/", routes::status::routes())
+        .mount("/", routes::settings::routes())
+        .mount("/", routes::config::routes())
         .mount("/ecos", routes::ecos::routes())
-        .manage(Arc::new(app_state))
+        .manage(app_state)
         .launch()
         .await?;
 
     Ok(())
 }
+
+/// Reload the config on `SIGHUP`, e.g. from `systemctl reload`
+#[cfg(unix)]
+async fn reload_on_sighup(state: Arc<AppState>) {
+    use ecactus_controller::audit::Actor;
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangups = signal(SignalKind::hangup()).expect("Failed to listen for SIGHUP");
+    while hangups.recv().await.is_some() {
+        // a rejected config is logged and audited, and the running one kept
+        let _ = state.reload_config(Actor::controller("SIGHUP")).await;
+    }
+}
@@ -0,0 +1,162 @@
+use crate::ecos::data_models::RunData;
+use prometheus::{
+    Encoder, Gauge, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec,
+    Opts, Registry, TextEncoder,
+};
+use std::sync::OnceLock;
+
+pub struct Metrics {
+    registry: Registry,
+    pub battery_soc: Gauge,
+    pub battery_power: Gauge,
+    pub eps_power: Gauge,
+    pub grid_power: Gauge,
+    pub home_power: Gauge,
+    pub meter_power: Gauge,
+    pub solar_power: Gauge,
+    pub sys_run_mode: IntGauge,
+    pub is_exist_solar: IntGauge,
+    pub sys_power_config: IntGauge,
+    pub charge_mode: IntGaugeVec,
+    pub mode_remaining_seconds: Gauge,
+    pub charge_power: Gauge,
+    pub ecos_requests: IntCounterVec,
+    pub ecos_errors: IntCounterVec,
+    pub ecos_latency: HistogramVec,
+    pub ecos_cache_hits: IntCounterVec,
+    pub ecos_logins: IntCounter,
+    pub settings_writes_skipped: IntCounter,
+    pub background_task_running: IntGauge,
+}
+
+/// The process-wide metrics, registered on first use
+pub fn metrics() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+    METRICS.get_or_init(Metrics::new)
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry =
+            Registry::new_custom(Some("ecactus".to_string()), None).expect("valid metrics prefix");
+
+        fn register<T: prometheus::core::Collector + Clone + 'static>(
+            registry: &Registry,
+            collector: T,
+        ) -> T {
+            registry
+                .register(Box::new(collector.clone()))
+                .expect("unique metric");
+            collector
+        }
+        let gauge = |name: &str, help: &str| register(&registry, Gauge::new(name, help).unwrap());
+        let int_gauge =
+            |name: &str, help: &str| register(&registry, IntGauge::new(name, help).unwrap());
+
+        Metrics {
+            battery_soc: gauge("battery_soc_percent", "Battery state of charge"),
+            battery_power: gauge("battery_power_watts", "Battery power"),
+            eps_power: gauge("eps_power_watts", "EPS (backup) load power"),
+            grid_power: gauge("grid_power_watts", "Grid power"),
+            home_power: gauge("home_power_watts", "Home load power"),
+            meter_power: gauge("meter_power_watts", "Meter power"),
+            solar_power: gauge("solar_power_watts", "PV power"),
+            sys_run_mode: int_gauge("sys_run_mode", "System run mode reported by ECOS"),
+            is_exist_solar: int_gauge("is_exist_solar", "Whether PV is present (1) or not (0)"),
+            sys_power_config: int_gauge("sys_power_config", "System power config reported by ECOS"),
+            charge_mode: register(
+                &registry,
+                IntGaugeVec::new(
+                    Opts::new(
+                        "charge_mode",
+                        "The current charge mode (1 for the active one)",
+                    ),
+                    &["mode"],
+                )
+                .unwrap(),
+            ),
+            mode_remaining_seconds: gauge(
+                "charge_mode_remaining_seconds",
+                "Seconds until the current charge mode expires (0 if it does not)",
+            ),
+            charge_power: gauge(
+                "charge_power_watts",
+                "Last computed charge power (negative when discharging)",
+            ),
+            ecos_requests: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new("ecos_requests_total", "Requests sent to ECOS"),
+                    &["endpoint"],
+                )
+                .unwrap(),
+            ),
+            ecos_errors: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new("ecos_errors_total", "Failed requests to ECOS"),
+                    &["endpoint"],
+                )
+                .unwrap(),
+            ),
+            ecos_latency: register(
+                &registry,
+                HistogramVec::new(
+                    HistogramOpts::new("ecos_request_duration_seconds", "ECOS request latency"),
+                    &["endpoint"],
+                )
+                .unwrap(),
+            ),
+            ecos_cache_hits: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new(
+                        "ecos_cache_hits_total",
+                        "ECOS responses served from the cache",
+                    ),
+                    &["endpoint"],
+                )
+                .unwrap(),
+            ),
+            ecos_logins: register(
+                &registry,
+                IntCounter::new("ecos_logins_total", "Logins to ECOS").unwrap(),
+            ),
+            settings_writes_skipped: register(
+                &registry,
+                IntCounter::new(
+                    "settings_writes_skipped_total",
+                    "Settings not written because they were within the hysteresis",
+                )
+                .unwrap(),
+            ),
+            background_task_running: int_gauge(
+                "background_task_running",
+                "Whether the charge mode background task is running (1) or not (0)",
+            ),
+            registry,
+        }
+    }
+
+    pub fn observe_run_data(&self, data: &RunData) {
+        self.battery_soc.set(data.batterySoc as f64);
+        self.battery_power.set(data.batteryPower as f64);
+        self.eps_power.set(data.epsPower as f64);
+        self.grid_power.set(data.gridPower as f64);
+        self.home_power.set(data.homePower as f64);
+        self.meter_power.set(data.meterPower as f64);
+        self.solar_power.set(data.solarPower as f64);
+        self.sys_run_mode.set(data.sysRunMode as i64);
+        self.is_exist_solar.set(data.isExistSolar as i64);
+        self.sys_power_config.set(data.sysPowerConfig as i64);
+    }
+
+    /// Encode all metrics in the Prometheus text format
+    pub fn encode(&self) -> String {
+        let mut buffer = vec![];
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("encode metrics");
+        String::from_utf8(buffer).expect("metrics are valid UTF-8")
+    }
+}
@@ -0,0 +1,151 @@
+use crate::audit::Actor;
+use crate::config::MqttConfig;
+use crate::ecos::data_models::RunData;
+use crate::home_assistant::{self, Controls};
+use crate::state::{AppState, ChargeMode};
+use crate::validation::validate_mode;
+use rocket::log::private::{info, warn};
+use rocket::serde::json::serde_json;
+use rocket::serde::Serialize;
+use rocket::tokio;
+use rocket::tokio::sync::Mutex;
+use rumqttc::{AsyncClient, Event, EventLoop, MqttOptions, Packet, QoS};
+use std::sync::Arc;
+use std::time::Duration;
+
+pub struct Mqtt {
+    pub client: AsyncClient,
+    pub config: MqttConfig,
+    pub controls: Mutex<Controls>,
+}
+
+impl Mqtt {
+    /// Create the client; the returned event loop must be driven by [`run`]
+    pub fn new(config: MqttConfig) -> (Self, EventLoop) {
+        let mut options = MqttOptions::new(&config.client_id, &config.host, config.port);
+        options.set_keep_alive(Duration::from_secs(30));
+        if let (Some(username), Some(password)) = (&config.username, &config.password) {
+            options.set_credentials(username, password);
+        }
+        let (client, eventloop) = AsyncClient::new(options, 64);
+        let mqtt = Mqtt {
+            client,
+            config,
+            controls: Mutex::new(Controls::default()),
+        };
+        (mqtt, eventloop)
+    }
+
+    /// Publish a retained JSON message
+    pub async fn publish_json<T: Serialize>(&self, topic: &str, value: &T) {
+        let payload = match serde_json::to_vec(value) {
+            Ok(payload) => payload,
+            Err(e) => {
+                warn!("Failed to serialize MQTT message for {}: {:?}", topic, e);
+                return;
+            }
+        };
+        if let Err(e) = self
+            .client
+            .publish(topic, QoS::AtLeastOnce, true, payload)
+            .await
+        {
+            warn!("Failed to publish to {}: {:?}", topic, e);
+        }
+    }
+
+    pub async fn publish_run_data(&self, run_data: &RunData) {
+        self.publish_json(&self.config.run_data_topic, run_data)
+            .await;
+    }
+
+    pub async fn publish_mode(&self, charge_mode: &ChargeMode) {
+        self.publish_json(&self.config.mode_topic, charge_mode)
+            .await;
+        if self.config.discovery {
+            let controls = {
+                let mut controls = self.controls.lock().await;
+                controls.update_from_mode(charge_mode);
+                controls.clone()
+            };
+            self.publish_json(&self.config.controls_topic, &controls)
+                .await;
+        }
+    }
+}
+
+/// Drive the MQTT connection and apply charge mode commands from the command topic.
+/// Commands have the same JSON shape as `POST /charge-mode`.
+/// With discovery enabled, the Home Assistant entities are announced and their commands handled too.
+pub async fn run(state: Arc<AppState>, mut eventloop: EventLoop) {
+    let Some(mqtt) = &state.mqtt else {
+        return;
+    };
+    let command_topic = mqtt.config.command_topic.clone();
+    let controls_prefix = format!("{}/", mqtt.config.controls_topic);
+    loop {
+        match eventloop.poll().await {
+            Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                info!(target: "app", "Connected to MQTT broker, subscribing to {}", command_topic);
+                // subscribe again after every reconnect
+                if let Err(e) = mqtt
+                    .client
+                    .subscribe(&command_topic, QoS::AtLeastOnce)
+                    .await
+                {
+                    warn!("Failed to subscribe to {}: {:?}", command_topic, e);
+                }
+                if mqtt.config.discovery {
+                    let filter = format!("{}+/set", controls_prefix);
+                    if let Err(e) = mqtt.client.subscribe(&filter, QoS::AtLeastOnce).await {
+                        warn!("Failed to subscribe to {}: {:?}", filter, e);
+                    }
+                    home_assistant::publish_discovery(&state).await;
+                }
+            }
+            Ok(Event::Incoming(Packet::Publish(publish))) if publish.topic == command_topic => {
+                handle_command(&state, &publish.topic, &publish.payload).await;
+            }
+            Ok(Event::Incoming(Packet::Publish(publish)))
+                if mqtt.config.discovery && publish.topic.starts_with(&controls_prefix) =>
+            {
+                let payload = String::from_utf8_lossy(&publish.payload);
+                home_assistant::handle_control(&state, &publish.topic, &payload).await;
+            }
+            Ok(_) => {}
+            Err(e) => {
+                warn!("MQTT connection error: {:?}", e);
+                tokio::time::sleep(Duration::from_secs(5)).await;
+            }
+        }
+    }
+}
+
+async fn handle_command(state: &Arc<AppState>, topic: &str, payload: &[u8]) {
+    let charge_mode: ChargeMode = match serde_json::from_slice(payload) {
+        Ok(charge_mode) => charge_mode,
+        Err(e) => {
+            warn!("Ignoring invalid charge mode command on {}: {:?}", topic, e);
+            return;
+        }
+    };
+    if let Err(e) = validate_mode(&charge_mode, &state.config()) {
+        warn!(
+            "Ignoring invalid charge mode command on {}: {:?}",
+            topic, e.errors
+        );
+        return;
+    }
+    info!(target: "app", "Charge mode command from MQTT: {:?}", charge_mode);
+    AppState::apply_mode(
+        state,
+        charge_mode.clone(),
+        Actor::Mqtt {
+            topic: topic.to_string(),
+        },
+    )
+    .await;
+    if let Some(mqtt) = &state.mqtt {
+        mqtt.publish_mode(&charge_mode).await;
+    }
+}
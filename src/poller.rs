@@ -0,0 +1,50 @@
+use crate::events::ControllerEvent;
+use crate::history::RunDataSample;
+use crate::state::AppState;
+use rocket::log::private::{info, warn};
+use rocket::tokio;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Poll the run data of the configured device, record it in the history store,
+/// push it to the `/events` subscribers and publish it to MQTT if configured
+pub async fn run(state: Arc<AppState>, interval: Duration) {
+    info!(target: "app", "Polling run data every {} s", interval.as_secs());
+    loop {
+        poll_once(&state).await;
+        tokio::time::sleep(interval).await;
+    }
+}
+
+pub async fn poll_once(state: &AppState) {
+    let run_data = match state
+        .ecos_client
+        .get_run_data(state.config().deviceId.clone())
+        .await
+    {
+        Ok(res) => res.data,
+        Err(e) => {
+            warn!("Failed to poll run data: {:?}", e);
+            return;
+        }
+    };
+    if run_data.batterySoc < 0.01 {
+        // NOTE: the server is returning null data, which we do not want in the history
+        warn!("Skipping run data sample with no battery SOC");
+        return;
+    }
+    if let Some(mqtt) = &state.mqtt {
+        mqtt.publish_run_data(&run_data).await;
+        mqtt.publish_mode(&state.current_mode.lock().await.clone())
+            .await;
+    }
+    let sample = RunDataSample::now(run_data);
+    state.emit(ControllerEvent::RunData {
+        timestamp: sample.timestamp,
+        data: sample.data.clone(),
+    });
+    if let Err(e) = state.history.append(&sample).await {
+        warn!("Failed to record run data: {:?}", e);
+    }
+    *state.last_run_data.lock().await = Some(sample);
+}
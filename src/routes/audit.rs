@@ -0,0 +1,38 @@
+use crate::audit::{AuditEntry, AuditKind};
+use crate::auth::ReadAccess;
+use crate::routes::params::{time_range, TimeParam};
+use crate::state::AppState;
+use rocket::http::Status;
+use rocket::response::status::Custom;
+use rocket::serde::json::Json;
+use rocket::{get, routes, State};
+use std::sync::Arc;
+
+/// Query the audit log, oldest first. `limit` keeps the most recent entries.
+#[get("/audit?<from>&<to>&<kind>&<limit>")]
+pub async fn get_audit(
+    _access: ReadAccess,
+    state: &State<Arc<AppState>>,
+    from: Option<TimeParam>,
+    to: Option<TimeParam>,
+    kind: Option<AuditKind>,
+    limit: Option<usize>,
+) -> Result<Json<Vec<AuditEntry>>, Custom<String>> {
+    let (from, to) = time_range(from, to);
+    let mut entries: Vec<AuditEntry> = state
+        .audit
+        .read_range(from, to)
+        .await
+        .map_err(|e| Custom(Status::InternalServerError, e.to_string()))?
+        .into_iter()
+        .filter(|e| kind.is_none_or(|k| e.event.kind() == k))
+        .collect();
+    if let Some(limit) = limit {
+        entries.drain(..entries.len().saturating_sub(limit));
+    }
+    Ok(Json(entries))
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![get_audit]
+}
@@ -1,45 +1,119 @@
+use crate::audit::Caller;
+use crate::auth::{ControlAccess, ReadAccess};
 use crate::state::AppState;
-use crate::state::ChargeMode;
-use rocket::serde::json::Json;
+use crate::state::{ChargeMode, ModeAdjustment};
+use crate::validation::{validate_mode, ValidationError};
+use rocket::response::status::Custom;
+use rocket::serde::json::{self, Json};
 use rocket::serde::Serialize;
-use rocket::{get, post, put, routes, State};
+use rocket::{get, patch, post, put, routes, State};
 use std::sync::Arc;
+use utoipa::ToSchema;
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 #[serde(crate = "rocket::serde", tag = "mode")]
 pub struct Message {
     message: String,
 }
 
+/// Set the charge mode. Invalid modes and malformed bodies get a 422 listing each invalid field.
+#[utoipa::path(
+    tag = "charge mode",
+    request_body = ChargeMode,
+    responses(
+        (status = 200, description = "The mode is applied", body = Message),
+        (status = 401, description = "Missing or unknown API key"),
+        (status = 403, description = "The API key lacks the control scope"),
+        (status = 422, description = "Invalid or malformed charge mode", body = ValidationError),
+    ),
+    security((), ("bearer" = []), ("api_key" = []))
+)]
 #[post("/charge-mode", data = "<charge_mode>")]
 pub async fn set_mode(
-    charge_mode: Json<ChargeMode>,
+    _access: ControlAccess,
+    charge_mode: Result<Json<ChargeMode>, json::Error<'_>>,
     state: &State<Arc<AppState>>,
-) -> Json<Message> {
-    state.update_mode(charge_mode.into_inner()).await;
-    AppState::start_task(&**state).await;
+    caller: Caller,
+) -> Result<Json<Message>, Custom<Json<ValidationError>>> {
+    let charge_mode = charge_mode
+        .map_err(|e| ValidationError::malformed(&e).into_response())?
+        .into_inner();
+    validate_mode(&charge_mode, &state.config()).map_err(ValidationError::into_response)?;
 
-    Json(Message {
+    AppState::apply_mode(state, charge_mode, caller.0).await;
+
+    Ok(Json(Message {
         message: "Charge mode update request sent".to_string(),
-    })
+    }))
 }
 
+/// Change parameters of the running mode without restarting it. Fields the mode does not have get a 422.
+#[utoipa::path(
+    tag = "charge mode",
+    request_body = ModeAdjustment,
+    responses(
+        (status = 200, description = "The adjusted mode", body = ChargeMode),
+        (status = 401, description = "Missing or unknown API key"),
+        (status = 403, description = "The API key lacks the control scope"),
+        (status = 422, description = "Invalid or malformed adjustment", body = ValidationError),
+    ),
+    security((), ("bearer" = []), ("api_key" = []))
+)]
+#[patch("/charge-mode", data = "<adjustment>")]
+pub async fn adjust_mode(
+    _access: ControlAccess,
+    adjustment: Result<Json<ModeAdjustment>, json::Error<'_>>,
+    state: &State<Arc<AppState>>,
+    caller: Caller,
+) -> Result<Json<ChargeMode>, Custom<Json<ValidationError>>> {
+    let adjustment = adjustment
+        .map_err(|e| ValidationError::malformed(&e).into_response())?
+        .into_inner();
+    AppState::adjust_mode(state, adjustment, caller.0)
+        .await
+        .map(Json)
+        .map_err(ValidationError::into_response)
+}
+
+/// Cancel the running mode and go back to self-sufficient
+#[utoipa::path(
+    tag = "charge mode",
+    responses(
+        (status = 200, description = "The mode is reset", body = Message),
+        (status = 401, description = "Missing or unknown API key"),
+        (status = 403, description = "The API key lacks the control scope"),
+    ),
+    security((), ("bearer" = []), ("api_key" = []))
+)]
 #[put("/charge-mode/reset")]
-pub async fn reset_mode(state: &State<Arc<AppState>>) -> Json<Message> {
+pub async fn reset_mode(
+    _access: ControlAccess,
+    state: &State<Arc<AppState>>,
+    caller: Caller,
+) -> Json<Message> {
     state.cancel_task().await;
-    state.reset_mode().await;
+    state.reset_mode(caller.0).await;
 
     Json(Message {
         message: "Charge mode reset".to_string(),
     })
 }
 
+/// The current charge mode
+#[utoipa::path(
+    tag = "charge mode",
+    responses(
+        (status = 200, description = "The current charge mode", body = ChargeMode),
+        (status = 401, description = "Missing or unknown API key"),
+    ),
+    security((), ("bearer" = []), ("api_key" = []))
+)]
 #[get("/charge-mode")]
-pub async fn get_mode(state: &State<Arc<AppState>>) -> Json<ChargeMode> {
+pub async fn get_mode(_access: ReadAccess, state: &State<Arc<AppState>>) -> Json<ChargeMode> {
     let current_mode = state.current_mode.lock().await.clone();
     Json(current_mode)
 }
 
 pub fn routes() -> Vec<rocket::Route> {
-    routes![set_mode, reset_mode, get_mode]
+    routes![set_mode, adjust_mode, reset_mode, get_mode]
 }
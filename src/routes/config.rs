@@ -0,0 +1,38 @@
+use crate::audit::Caller;
+use crate::auth::ControlAccess;
+use crate::state::AppState;
+use crate::validation::ValidationError;
+use rocket::http::Status;
+use rocket::response::status::Custom;
+use rocket::serde::json::Json;
+use rocket::{post, routes, State};
+use std::sync::Arc;
+
+/// Read `[app]` in the config file again. Running modes use it from their next check; an invalid
+/// file gets a 422 and the running config is kept. Other tables need a restart.
+#[utoipa::path(
+    tag = "config",
+    responses(
+        (status = 204, description = "The config is reloaded"),
+        (status = 401, description = "Missing or unknown API key"),
+        (status = 403, description = "The API key lacks the control scope"),
+        (status = 422, description = "The config file is missing or invalid", body = ValidationError),
+    ),
+    security((), ("bearer" = []), ("api_key" = []))
+)]
+#[post("/config/reload")]
+pub async fn reload_config(
+    _access: ControlAccess,
+    state: &State<Arc<AppState>>,
+    caller: Caller,
+) -> Result<Status, Custom<Json<ValidationError>>> {
+    state
+        .reload_config(caller.0)
+        .await
+        .map(|_| Status::NoContent)
+        .map_err(ValidationError::into_response)
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![reload_config]
+}
@@ -1,50 +1,233 @@
-use crate::ecos::data_models::{ChargeModeSettingsResponse, DevicesResponse, RunDataResponse};
+use crate::audit::{Actor, Caller};
+use crate::auth::{ControlAccess, ReadAccess};
+use crate::config::{load_config, write_app_settings, Config};
+use crate::ecos::cache::Cached;
+use crate::ecos::data_models::{
+    ChargeModeSettingsPatch, ChargeModeSettingsRequest, ChargeModeSettingsResponse,
+    DevicesResponse, EcosResponse, RunDataResponse,
+};
 use crate::state::AppState;
+use crate::validation::{validate_config, validate_settings, FieldError, ValidationError};
+use rocket::http::{Header, Status};
 use rocket::response::status::Custom;
-use rocket::serde::json::Json;
-use rocket::{get, routes, State};
+use rocket::serde::json::{self, Json};
+use rocket::serde::Serialize;
+use rocket::{get, put, routes, Responder, State};
+use std::path::Path;
 use std::sync::Arc;
 
+/// A JSON response with an `Age` header, the seconds since it was fetched from ECOS
+#[derive(Responder)]
+pub struct CachedJson<T: Serialize> {
+    body: Json<T>,
+    age: Header<'static>,
+}
+
+impl<T: Serialize> From<Cached<T>> for CachedJson<T> {
+    fn from(cached: Cached<T>) -> Self {
+        CachedJson {
+            body: Json(cached.value),
+            age: Header::new("Age", cached.age.as_secs().to_string()),
+        }
+    }
+}
+
+/// The devices of the ECOS account
+#[utoipa::path(
+    context_path = "/ecos",
+    tag = "ecos",
+    responses(
+        (status = 200, description = "The ECOS response, with its age in seconds in the `Age` header", body = DevicesResponse),
+        (status = 401, description = "Missing or unknown API key"),
+        (status = 500, description = "The ECOS request failed"),
+    ),
+    security((), ("bearer" = []), ("api_key" = []))
+)]
 #[get("/devices")]
-async fn get_devices(
+pub async fn get_devices(
+    _access: ReadAccess,
     state: &State<Arc<AppState>>,
-) -> Result<Json<DevicesResponse>, Custom<String>> {
+) -> Result<CachedJson<DevicesResponse>, Custom<String>> {
     state
         .ecos_client
-        .get_devices()
+        .get_devices_cached()
         .await
-        .map(Json)
+        .map(CachedJson::from)
         .map_err(|e| Custom(rocket::http::Status::InternalServerError, e.to_string()))
 }
 
+/// The live power flows of a device
+#[utoipa::path(
+    context_path = "/ecos",
+    tag = "ecos",
+    params(("device_id" = Option<String>, Query, description = "The device, `deviceId` from the config by default")),
+    responses(
+        (status = 200, description = "The ECOS response, with its age in seconds in the `Age` header", body = RunDataResponse),
+        (status = 401, description = "Missing or unknown API key"),
+        (status = 500, description = "The ECOS request failed"),
+    ),
+    security((), ("bearer" = []), ("api_key" = []))
+)]
 #[get("/run-data?<device_id>")]
-async fn get_run_data(
+pub async fn get_run_data(
+    _access: ReadAccess,
     state: &State<Arc<AppState>>,
     device_id: Option<String>,
-) -> Result<Json<RunDataResponse>, Custom<String>> {
-    let device_id = device_id.unwrap_or_else(|| state.app_config.deviceId.clone());
+) -> Result<CachedJson<RunDataResponse>, Custom<String>> {
+    let device_id = device_id.unwrap_or_else(|| state.config().deviceId.clone());
     state
         .ecos_client
-        .get_run_data(device_id)
+        .get_run_data_cached(device_id)
         .await
-        .map(Json)
+        .map(CachedJson::from)
         .map_err(|e| Custom(rocket::http::Status::InternalServerError, e.to_string()))
 }
 
+/// The charge mode settings of a device
+#[utoipa::path(
+    context_path = "/ecos",
+    tag = "ecos",
+    params(("device_id" = Option<String>, Query, description = "The device, `deviceId` from the config by default")),
+    responses(
+        (status = 200, description = "The ECOS response, with its age in seconds in the `Age` header", body = ChargeModeSettingsResponse),
+        (status = 401, description = "Missing or unknown API key"),
+        (status = 500, description = "The ECOS request failed"),
+    ),
+    security((), ("bearer" = []), ("api_key" = []))
+)]
 #[get("/charge-mode-settings?<device_id>")]
-async fn get_charge_mode_settings(
+pub async fn get_charge_mode_settings(
+    _access: ReadAccess,
     state: &State<Arc<AppState>>,
     device_id: Option<String>,
-) -> Result<Json<ChargeModeSettingsResponse>, Custom<String>> {
-    let device_id = device_id.unwrap_or_else(|| state.app_config.deviceId.clone());
+) -> Result<CachedJson<ChargeModeSettingsResponse>, Custom<String>> {
+    let device_id = device_id.unwrap_or_else(|| state.config().deviceId.clone());
     state
         .ecos_client
-        .get_charge_mode_settings(&device_id)
+        .get_charge_mode_settings_cached(&device_id)
         .await
-        .map(Json)
+        .map(CachedJson::from)
         .map_err(|e| Custom(rocket::http::Status::InternalServerError, e.to_string()))
 }
 
+/// A rejected settings change, or a failed ECOS request or config write
+#[derive(Responder)]
+pub enum SettingsError {
+    Invalid(Custom<Json<ValidationError>>),
+    Failed(Custom<String>),
+}
+
+impl From<ValidationError> for SettingsError {
+    fn from(error: ValidationError) -> Self {
+        SettingsError::Invalid(error.into_response())
+    }
+}
+
+impl SettingsError {
+    pub(crate) fn failed(e: impl ToString) -> Self {
+        SettingsError::Failed(Custom(Status::InternalServerError, e.to_string()))
+    }
+}
+
+/// The config file to persist `patch` to, if the config stays valid with it. Only the defaults of
+/// the configured device can be changed.
+fn check_persist<'a>(
+    state: &'a AppState,
+    device_id: &str,
+    patch: &ChargeModeSettingsPatch,
+) -> Result<&'a Path, SettingsError> {
+    let path = state
+        .config_path
+        .as_deref()
+        .ok_or_else(|| SettingsError::failed("No config file to persist to"))?;
+    if device_id != state.config().deviceId {
+        return Err(ValidationError::settings(vec![FieldError {
+            field: "persist".to_string(),
+            message: format!(
+                "only the settings of the configured device can be persisted, not {}",
+                device_id
+            ),
+        }])
+        .into());
+    }
+    let mut app_config = load_config::<Config>(path)
+        .map_err(SettingsError::failed)?
+        .app;
+    app_config.apply_settings(patch);
+    validate_config(&app_config)?;
+    Ok(path)
+}
+
+/// Change some of the charge mode settings of a device. The fields are merged with the current settings,
+/// and the result is validated before it is written. With `persist`, they also become the defaults in the config file,
+/// which is reloaded.
+/// The running mode may overwrite them at its next check.
+#[utoipa::path(
+    context_path = "/ecos",
+    tag = "ecos",
+    params(
+        ("device_id" = Option<String>, Query, description = "The device, `deviceId` from the config by default"),
+        ("persist" = Option<bool>, Query, description = "Also write the fields to `[app]` in the config file"),
+    ),
+    request_body = ChargeModeSettingsPatch,
+    responses(
+        (status = 200, description = "The ECOS response", body = EcosResponse),
+        (status = 401, description = "Missing or unknown API key"),
+        (status = 403, description = "The API key lacks the control scope"),
+        (status = 422, description = "Invalid or malformed settings, or persisted defaults that would make the config invalid", body = ValidationError),
+        (status = 500, description = "The ECOS request or the config write failed"),
+    ),
+    security((), ("bearer" = []), ("api_key" = []))
+)]
+#[put("/charge-mode-settings?<device_id>&<persist>", data = "<patch>")]
+pub async fn put_charge_mode_settings(
+    _access: ControlAccess,
+    state: &State<Arc<AppState>>,
+    device_id: Option<String>,
+    persist: Option<bool>,
+    patch: Result<Json<ChargeModeSettingsPatch>, json::Error<'_>>,
+    caller: Caller,
+) -> Result<Json<EcosResponse>, SettingsError> {
+    let patch = patch
+        .map_err(|e| ValidationError::settings(ValidationError::malformed(&e).errors))?
+        .into_inner();
+    let device_id = device_id.unwrap_or_else(|| state.config().deviceId.clone());
+    let config_path = if persist.unwrap_or(false) {
+        Some(check_persist(state, &device_id, &patch)?)
+    } else {
+        None
+    };
+
+    // changes made in the vendor app since the last read must not be overwritten
+    let current = state
+        .ecos_client
+        .get_charge_mode_settings_fresh(&device_id)
+        .await
+        .map_err(SettingsError::failed)?
+        .data;
+    let mut request = ChargeModeSettingsRequest::from_settings(device_id, &current);
+    patch.apply(&mut request);
+    validate_settings(&request)?;
+
+    let response = state
+        .write_settings_as(request, caller.0)
+        .await
+        .map_err(SettingsError::failed)?;
+    if let Some(path) = config_path {
+        write_app_settings(path, &patch).map_err(SettingsError::failed)?;
+        // checked above, so only a concurrent edit of the file fails here, which the audit log records
+        let _ = state
+            .reload_config(Actor::controller("persist charge mode settings"))
+            .await;
+    }
+    Ok(Json(response))
+}
+
 pub fn routes() -> Vec<rocket::Route> {
-    routes![get_devices, get_run_data, get_charge_mode_settings]
+    routes![
+        get_devices,
+        get_run_data,
+        get_charge_mode_settings,
+        put_charge_mode_settings
+    ]
 }
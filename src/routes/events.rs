@@ -0,0 +1,51 @@
+use crate::auth::ReadAccess;
+use crate::events::ControllerEvent;
+use crate::state::AppState;
+use rocket::response::stream::{Event, EventStream};
+use rocket::tokio::select;
+use rocket::tokio::sync::broadcast::error::RecvError;
+use rocket::{get, routes, Shutdown, State};
+use std::sync::Arc;
+
+/// Stream live events. New subscribers first get the latest run data snapshot, if any.
+#[get("/events")]
+pub async fn get_events(
+    _access: ReadAccess,
+    state: &State<Arc<AppState>>,
+    mut shutdown: Shutdown,
+) -> EventStream![] {
+    let mut receiver = state.events.subscribe();
+    let latest = state
+        .last_run_data
+        .lock()
+        .await
+        .clone()
+        .map(|sample| ControllerEvent::RunData {
+            timestamp: sample.timestamp,
+            data: sample.data,
+        });
+
+    EventStream! {
+        if let Some(event) = latest {
+            yield Event::json(&event).event(event.name());
+        }
+        loop {
+            let event = select! {
+                // deliver pending events before closing on shutdown
+                biased;
+                event = receiver.recv() => match event {
+                    Ok(event) => event,
+                    Err(RecvError::Closed) => break,
+                    // a slow subscriber skips the events it missed
+                    Err(RecvError::Lagged(_)) => continue,
+                },
+                _ = &mut shutdown => break,
+            };
+            yield Event::json(&event).event(event.name());
+        }
+    }
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![get_events]
+}
@@ -0,0 +1,79 @@
+use crate::auth::ReadAccess;
+use crate::export::{ExportFormat, Table};
+use crate::routes::params::{time_range, TimeParam};
+use crate::state::AppState;
+use rocket::http::{ContentType, Header, Status};
+use rocket::response::status::Custom;
+use rocket::{get, routes, Responder, State};
+use std::sync::Arc;
+
+#[derive(Responder)]
+pub struct Download {
+    body: (ContentType, Vec<u8>),
+    disposition: Header<'static>,
+}
+
+fn download(table: Table, format: ExportFormat) -> Result<Download, Custom<String>> {
+    let body = table
+        .write(format)
+        .map_err(|e| Custom(Status::InternalServerError, e.to_string()))?;
+    let content_type = match format {
+        ExportFormat::Csv => ContentType::CSV,
+        ExportFormat::Parquet => ContentType::new("application", "vnd.apache.parquet"),
+    };
+    Ok(Download {
+        body: (content_type, body),
+        disposition: Header::new(
+            "Content-Disposition",
+            format!(
+                "attachment; filename=\"{}.{}\"",
+                table.name,
+                format.extension()
+            ),
+        ),
+    })
+}
+
+#[get("/export/run-data?<from>&<to>&<format>")]
+pub async fn export_run_data(
+    _access: ReadAccess,
+    state: &State<Arc<AppState>>,
+    from: Option<TimeParam>,
+    to: Option<TimeParam>,
+    format: Option<ExportFormat>,
+) -> Result<Download, Custom<String>> {
+    let (from, to) = time_range(from, to);
+    let samples = state
+        .history
+        .read_range(from, to)
+        .await
+        .map_err(|e| Custom(Status::InternalServerError, e.to_string()))?;
+    download(
+        Table::run_data(&samples),
+        format.unwrap_or(ExportFormat::Csv),
+    )
+}
+
+#[get("/export/charge-modes?<from>&<to>&<format>")]
+pub async fn export_charge_modes(
+    _access: ReadAccess,
+    state: &State<Arc<AppState>>,
+    from: Option<TimeParam>,
+    to: Option<TimeParam>,
+    format: Option<ExportFormat>,
+) -> Result<Download, Custom<String>> {
+    let (from, to) = time_range(from, to);
+    let changes = state
+        .mode_history
+        .read_range(from, to)
+        .await
+        .map_err(|e| Custom(Status::InternalServerError, e.to_string()))?;
+    download(
+        Table::mode_changes(&changes),
+        format.unwrap_or(ExportFormat::Csv),
+    )
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![export_run_data, export_charge_modes]
+}
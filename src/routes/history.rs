@@ -0,0 +1,57 @@
+use crate::auth::ReadAccess;
+use crate::history::{energy_totals, power_series, EnergyTotals, PowerPoint, Resolution};
+use crate::routes::params::{time_range, TimeParam};
+use crate::state::AppState;
+use rocket::http::Status;
+use rocket::response::status::Custom;
+use rocket::serde::json::Json;
+use rocket::{get, routes, State};
+use std::sync::Arc;
+
+#[get("/history?<from>&<to>&<resolution>")]
+pub async fn get_history(
+    _access: ReadAccess,
+    state: &State<Arc<AppState>>,
+    from: Option<TimeParam>,
+    to: Option<TimeParam>,
+    resolution: Option<Resolution>,
+) -> Result<Json<Vec<PowerPoint>>, Custom<String>> {
+    let (from, to) = time_range(from, to);
+    let samples = state
+        .history
+        .read_range(from, to)
+        .await
+        .map_err(|e| Custom(Status::InternalServerError, e.to_string()))?;
+    Ok(Json(power_series(
+        &samples,
+        resolution.unwrap_or(Resolution::Raw),
+    )))
+}
+
+#[get("/history/energy?<from>&<to>&<resolution>")]
+pub async fn get_energy(
+    _access: ReadAccess,
+    state: &State<Arc<AppState>>,
+    from: Option<TimeParam>,
+    to: Option<TimeParam>,
+    resolution: Option<Resolution>,
+) -> Result<Json<Vec<EnergyTotals>>, Custom<String>> {
+    let resolution = resolution.unwrap_or(Resolution::Hour);
+    if resolution == Resolution::Raw {
+        return Err(Custom(
+            Status::BadRequest,
+            "Energy totals need a bucket resolution (minute, hour, day or month)".to_string(),
+        ));
+    }
+    let (from, to) = time_range(from, to);
+    let samples = state
+        .history
+        .read_range(from, to)
+        .await
+        .map_err(|e| Custom(Status::InternalServerError, e.to_string()))?;
+    Ok(Json(energy_totals(&samples, resolution)))
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![get_history, get_energy]
+}
@@ -0,0 +1,46 @@
+use crate::auth::ReadAccess;
+use crate::metrics::metrics;
+use crate::state::{AppState, ChargeMode};
+use rocket::http::ContentType;
+use rocket::{get, routes, State};
+use std::sync::Arc;
+use std::time::Instant;
+
+#[get("/metrics")]
+pub async fn get_metrics(
+    _access: ReadAccess,
+    state: &State<Arc<AppState>>,
+) -> (ContentType, String) {
+    let metrics = metrics();
+
+    let current_mode = state.current_mode.lock().await.name();
+    for name in ChargeMode::NAMES {
+        metrics
+            .charge_mode
+            .with_label_values(&[name])
+            .set((name == current_mode) as i64);
+    }
+    let remaining = state
+        .expiration
+        .lock()
+        .await
+        .map(|e| e.saturating_duration_since(Instant::now()).as_secs_f64())
+        .unwrap_or(0.0);
+    metrics.mode_remaining_seconds.set(remaining);
+    let running = state
+        .background_task
+        .lock()
+        .await
+        .as_ref()
+        .is_some_and(|task| !task.is_finished());
+    metrics.background_task_running.set(running as i64);
+
+    (
+        ContentType::new("text", "plain").with_params(("version", "0.0.4")),
+        metrics.encode(),
+    )
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![get_metrics]
+}
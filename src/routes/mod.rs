@@ -1,2 +1,12 @@
+pub mod audit;
 pub mod charge_mode;
+pub mod config;
 pub mod ecos;
+pub mod events;
+pub mod export;
+pub mod history;
+pub mod metrics;
+pub mod openapi;
+pub mod params;
+pub mod settings;
+pub mod status;
@@ -0,0 +1,89 @@
+use crate::ecos::data_models::{
+    ChargeModeSettings, ChargeModeSettingsPatch, ChargeModeSettingsRequest,
+    ChargeModeSettingsResponse, ChargeSchedule, Device, DevicesResponse, EcosResponse, RunData,
+    RunDataResponse,
+};
+use crate::routes::{charge_mode, config, ecos, settings, status};
+use crate::snapshots::{SettingsDiff, SettingsSnapshot};
+use crate::state::{ChargeMode, ControlStatus, ModeAdjustment};
+use crate::validation::{FieldError, ValidationError};
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+use utoipa_swagger_ui::SwaggerUi;
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "ecactus-controller"),
+    paths(
+        charge_mode::get_mode,
+        charge_mode::set_mode,
+        charge_mode::adjust_mode,
+        charge_mode::reset_mode,
+        ecos::get_devices,
+        ecos::get_run_data,
+        ecos::get_charge_mode_settings,
+        ecos::put_charge_mode_settings,
+        status::get_status,
+        settings::save_snapshot,
+        settings::list_snapshots,
+        settings::diff_snapshot,
+        settings::restore_snapshot,
+        config::reload_config,
+    ),
+    components(schemas(
+        ChargeMode,
+        ModeAdjustment,
+        charge_mode::Message,
+        ValidationError,
+        FieldError,
+        DevicesResponse,
+        Device,
+        RunDataResponse,
+        RunData,
+        ChargeModeSettingsResponse,
+        ChargeModeSettings,
+        ChargeSchedule,
+        ChargeModeSettingsRequest,
+        status::Status,
+        status::TaskState,
+        ControlStatus,
+        EcosResponse,
+        ChargeModeSettingsPatch,
+        settings::SnapshotRequest,
+        SettingsSnapshot,
+        SettingsDiff,
+    )),
+    modifiers(&Security),
+    tags(
+        (name = "charge mode", description = "Set and read the charge mode"),
+        (name = "ecos", description = "Passthrough to the ECOS API"),
+        (name = "status", description = "The state of the controller"),
+        (name = "settings", description = "Save and restore the device settings"),
+        (name = "config", description = "The config file"),
+    )
+)]
+pub struct ApiDoc;
+
+/// The API key schemes of `[auth]`; the routes also accept no key when none are configured
+struct Security;
+
+impl Modify for Security {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+        );
+        components.add_security_scheme(
+            "api_key",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("X-API-Key"))),
+        );
+    }
+}
+
+/// `/openapi.json` and the Swagger UI at `/docs`
+pub fn routes() -> Vec<rocket::Route> {
+    SwaggerUi::new("/docs/<_..>")
+        .url("/openapi.json", ApiDoc::openapi())
+        .into()
+}
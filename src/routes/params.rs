@@ -0,0 +1,39 @@
+use chrono::{DateTime, Local, NaiveDate, TimeZone};
+use rocket::form::{self, FromFormField, ValueField};
+
+/// A point in time given as a query parameter,
+/// either an RFC 3339 timestamp or a local date (`YYYY-MM-DD`, meaning midnight)
+#[derive(Debug, Clone, Copy)]
+pub struct TimeParam(pub DateTime<Local>);
+
+impl TimeParam {
+    pub fn parse(value: &str) -> Option<DateTime<Local>> {
+        if let Ok(ts) = DateTime::parse_from_rfc3339(value) {
+            return Some(ts.with_timezone(&Local));
+        }
+        NaiveDate::parse_from_str(value, "%Y-%m-%d")
+            .ok()
+            .and_then(|d| d.and_hms_opt(0, 0, 0))
+            .and_then(|t| Local.from_local_datetime(&t).earliest())
+    }
+}
+
+impl<'v> FromFormField<'v> for TimeParam {
+    fn from_value(field: ValueField<'v>) -> form::Result<'v, Self> {
+        TimeParam::parse(field.value).map(TimeParam).ok_or_else(|| {
+            form::Error::validation("expected an RFC 3339 timestamp or YYYY-MM-DD").into()
+        })
+    }
+}
+
+/// Resolve an optional `from`/`to` pair, defaulting to the last 24 hours
+pub fn time_range(
+    from: Option<TimeParam>,
+    to: Option<TimeParam>,
+) -> (DateTime<Local>, DateTime<Local>) {
+    let to = to.map(|t| t.0).unwrap_or_else(Local::now);
+    let from = from
+        .map(|t| t.0)
+        .unwrap_or_else(|| to - chrono::Duration::hours(24));
+    (from, to)
+}
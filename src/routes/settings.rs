@@ -0,0 +1,170 @@
+use crate::audit::Caller;
+use crate::auth::{ControlAccess, ReadAccess};
+use crate::ecos::data_models::{ChargeModeSettingsRequest, EcosResponse};
+use crate::routes::ecos::SettingsError;
+use crate::snapshots::{self, SettingsDiff, SettingsSnapshot};
+use crate::state::AppState;
+use crate::validation::{FieldError, ValidationError};
+use chrono::Local;
+use rocket::http::Status;
+use rocket::response::status::Custom;
+use rocket::serde::json::{self, Json};
+use rocket::serde::Deserialize;
+use rocket::{get, post, routes, State};
+use std::sync::Arc;
+use utoipa::ToSchema;
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(crate = "rocket::serde")]
+pub struct SnapshotRequest {
+    pub name: String,
+}
+
+async fn find_snapshot(state: &AppState, name: &str) -> Result<SettingsSnapshot, Custom<String>> {
+    state
+        .snapshots
+        .read_all()
+        .await
+        .map_err(|e| Custom(Status::InternalServerError, e.to_string()))?
+        .into_iter()
+        .rev()
+        .find(|snapshot| snapshot.name == name)
+        .ok_or_else(|| Custom(Status::NotFound, format!("No snapshot named {}", name)))
+}
+
+/// Save the current settings of the device under a name, replacing an older snapshot of that name
+#[utoipa::path(
+    tag = "settings",
+    request_body = SnapshotRequest,
+    responses(
+        (status = 200, description = "The saved snapshot", body = SettingsSnapshot),
+        (status = 401, description = "Missing or unknown API key"),
+        (status = 403, description = "The API key lacks the control scope"),
+        (status = 422, description = "Invalid name or malformed body", body = ValidationError),
+        (status = 500, description = "The ECOS request failed"),
+    ),
+    security((), ("bearer" = []), ("api_key" = []))
+)]
+#[post("/settings/snapshots", data = "<request>")]
+pub async fn save_snapshot(
+    _access: ControlAccess,
+    state: &State<Arc<AppState>>,
+    request: Result<Json<SnapshotRequest>, json::Error<'_>>,
+) -> Result<Json<SettingsSnapshot>, SettingsError> {
+    let request = request
+        .map_err(|e| ValidationError::snapshot(ValidationError::malformed(&e).errors))?
+        .into_inner();
+    snapshots::check_name(&request.name).map_err(|message| {
+        ValidationError::snapshot(vec![FieldError {
+            field: "name".to_string(),
+            message,
+        }])
+    })?;
+    let device_id = state.config().deviceId.clone();
+    let settings = state
+        .ecos_client
+        .get_charge_mode_settings_fresh(&device_id)
+        .await
+        .map_err(SettingsError::failed)?
+        .data;
+    let snapshot = SettingsSnapshot {
+        name: request.name,
+        timestamp: Local::now(),
+        device_id,
+        settings,
+    };
+    state
+        .snapshots
+        .append(&snapshot)
+        .await
+        .map_err(SettingsError::failed)?;
+    Ok(Json(snapshot))
+}
+
+/// The saved snapshots, the latest of each name
+#[utoipa::path(
+    tag = "settings",
+    responses(
+        (status = 200, description = "The snapshots, oldest first", body = Vec<SettingsSnapshot>),
+        (status = 401, description = "Missing or unknown API key"),
+    ),
+    security((), ("bearer" = []), ("api_key" = []))
+)]
+#[get("/settings/snapshots")]
+pub async fn list_snapshots(
+    _access: ReadAccess,
+    state: &State<Arc<AppState>>,
+) -> Result<Json<Vec<SettingsSnapshot>>, Custom<String>> {
+    let all = state
+        .snapshots
+        .read_all()
+        .await
+        .map_err(|e| Custom(Status::InternalServerError, e.to_string()))?;
+    Ok(Json(snapshots::latest(all)))
+}
+
+/// The settings that differ between a snapshot and the device
+#[utoipa::path(
+    tag = "settings",
+    params(("name" = String, Path, description = "The snapshot")),
+    responses(
+        (status = 200, description = "The differing fields, empty when the device matches the snapshot", body = Vec<SettingsDiff>),
+        (status = 401, description = "Missing or unknown API key"),
+        (status = 404, description = "No snapshot of that name"),
+        (status = 500, description = "The ECOS request failed"),
+    ),
+    security((), ("bearer" = []), ("api_key" = []))
+)]
+#[get("/settings/snapshots/<name>/diff")]
+pub async fn diff_snapshot(
+    _access: ReadAccess,
+    state: &State<Arc<AppState>>,
+    name: &str,
+) -> Result<Json<Vec<SettingsDiff>>, Custom<String>> {
+    let snapshot = find_snapshot(state, name).await?;
+    let live = state
+        .ecos_client
+        .get_charge_mode_settings_fresh(&snapshot.device_id)
+        .await
+        .map_err(|e| Custom(Status::InternalServerError, e.to_string()))?
+        .data;
+    Ok(Json(snapshots::diff(&snapshot.settings, &live)))
+}
+
+/// Write the settings of a snapshot back to the device. The running mode may overwrite them at its next check.
+#[utoipa::path(
+    tag = "settings",
+    params(("name" = String, Path, description = "The snapshot")),
+    responses(
+        (status = 200, description = "The ECOS response", body = EcosResponse),
+        (status = 401, description = "Missing or unknown API key"),
+        (status = 403, description = "The API key lacks the control scope"),
+        (status = 404, description = "No snapshot of that name"),
+        (status = 500, description = "The ECOS request failed"),
+    ),
+    security((), ("bearer" = []), ("api_key" = []))
+)]
+#[post("/settings/snapshots/<name>/restore")]
+pub async fn restore_snapshot(
+    _access: ControlAccess,
+    state: &State<Arc<AppState>>,
+    name: &str,
+    caller: Caller,
+) -> Result<Json<EcosResponse>, Custom<String>> {
+    let snapshot = find_snapshot(state, name).await?;
+    let request = ChargeModeSettingsRequest::from_settings(snapshot.device_id, &snapshot.settings);
+    state
+        .write_settings_as(request, caller.0)
+        .await
+        .map(Json)
+        .map_err(|e| Custom(Status::InternalServerError, e))
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![
+        save_snapshot,
+        list_snapshots,
+        diff_snapshot,
+        restore_snapshot
+    ]
+}
@@ -0,0 +1,70 @@
+use crate::auth::ReadAccess;
+use crate::state::{AppState, ChargeMode, ControlStatus};
+use rocket::serde::json::Json;
+use rocket::serde::Serialize;
+use rocket::{get, routes, State};
+use std::sync::Arc;
+use std::time::Instant;
+use utoipa::ToSchema;
+
+/// The state of the background task of the current mode
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, ToSchema)]
+#[serde(crate = "rocket::serde", rename_all = "kebab-case")]
+pub enum TaskState {
+    /// No task was started yet
+    None,
+    Running,
+    /// The task is done, as expected for the self-sufficient mode
+    Finished,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(crate = "rocket::serde")]
+pub struct Status {
+    pub mode: ChargeMode,
+    /// Seconds until the current mode ends, for timed modes
+    pub remaining_seconds: Option<u64>,
+    pub task: TaskState,
+    /// False when the task of a timed mode stopped before the mode ended
+    pub healthy: bool,
+    #[serde(flatten)]
+    pub control: ControlStatus,
+}
+
+/// The current mode, its timing and what the controller last did
+#[utoipa::path(
+    tag = "status",
+    responses(
+        (status = 200, description = "The controller status", body = Status),
+        (status = 401, description = "Missing or unknown API key"),
+    ),
+    security((), ("bearer" = []), ("api_key" = []))
+)]
+#[get("/status")]
+pub async fn get_status(_access: ReadAccess, state: &State<Arc<AppState>>) -> Json<Status> {
+    let mode = state.current_mode.lock().await.clone();
+    let remaining_seconds = state
+        .expiration
+        .lock()
+        .await
+        .map(|e| e.saturating_duration_since(Instant::now()).as_secs());
+    let task = match state.background_task.lock().await.as_ref() {
+        None => TaskState::None,
+        Some(task) if task.is_finished() => TaskState::Finished,
+        Some(_) => TaskState::Running,
+    };
+    let timed = remaining_seconds.is_some_and(|remaining| remaining > 0);
+    let control = state.control.lock().await.clone();
+
+    Json(Status {
+        mode,
+        remaining_seconds,
+        task,
+        healthy: !timed || task == TaskState::Running,
+        control,
+    })
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![get_status]
+}
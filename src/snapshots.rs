@@ -0,0 +1,136 @@
+use crate::ecos::data_models::ChargeModeSettings;
+use crate::storage::Timestamped;
+use chrono::{DateTime, Local};
+use rocket::serde::json::{serde_json, Value};
+use rocket::serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// Longest snapshot name
+pub const MAX_NAME_LENGTH: usize = 64;
+
+/// The settings of a device saved under a name, to write back later
+#[derive(Serialize, Deserialize, Clone, Debug, ToSchema)]
+#[serde(crate = "rocket::serde")]
+pub struct SettingsSnapshot {
+    pub name: String,
+    pub timestamp: DateTime<Local>,
+    pub device_id: String,
+    pub settings: ChargeModeSettings,
+}
+
+impl Timestamped for SettingsSnapshot {
+    fn timestamp(&self) -> DateTime<Local> {
+        self.timestamp
+    }
+}
+
+/// A setting that differs between a snapshot and the live settings
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, ToSchema)]
+#[serde(crate = "rocket::serde")]
+pub struct SettingsDiff {
+    pub field: String,
+    #[schema(value_type = Object)]
+    pub snapshot: Value,
+    #[schema(value_type = Object)]
+    pub live: Value,
+}
+
+/// Names are used in URLs, so they are limited to letters, digits, `-`, `_` and `.`
+pub fn check_name(name: &str) -> Result<(), String> {
+    if name.is_empty() || name.len() > MAX_NAME_LENGTH {
+        return Err(format!(
+            "the name must have 1 to {} characters",
+            MAX_NAME_LENGTH
+        ));
+    }
+    if !name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+    {
+        return Err("the name may only contain letters, digits, '-', '_' and '.'".to_string());
+    }
+    Ok(())
+}
+
+/// The latest snapshot of each name, in the order they were saved. Saving a name again replaces it.
+pub fn latest(snapshots: Vec<SettingsSnapshot>) -> Vec<SettingsSnapshot> {
+    let mut latest: Vec<SettingsSnapshot> = vec![];
+    for snapshot in snapshots {
+        latest.retain(|s| s.name != snapshot.name);
+        latest.push(snapshot);
+    }
+    latest
+}
+
+/// The settings that differ, by field name
+pub fn diff(snapshot: &ChargeModeSettings, live: &ChargeModeSettings) -> Vec<SettingsDiff> {
+    let (Ok(Value::Object(snapshot)), Ok(Value::Object(mut live))) =
+        (serde_json::to_value(snapshot), serde_json::to_value(live))
+    else {
+        return vec![];
+    };
+    snapshot
+        .into_iter()
+        // the serde tag of the model, not a setting
+        .filter(|(field, _)| field != "ecos")
+        .filter_map(|(field, value)| {
+            let live = live.remove(&field).unwrap_or(Value::Null);
+            (value != live).then_some(SettingsDiff {
+                field,
+                snapshot: value,
+                live,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::serde::json::json;
+
+    fn settings(min_capacity: i32, max_feed_in: i32) -> ChargeModeSettings {
+        serde_json::from_value(json!({
+            "minCapacity": min_capacity, "chargeUseMode": 0, "maxFeedIn": max_feed_in,
+            "epsBatteryMin": 10, "dischargeToGridFlag": 0, "selfSoc": 10, "selfEpsBat": 10,
+            "selfFeedIn": 100, "regularSoc": 10, "regularEpsBat": 10, "regularFeedIn": 100,
+            "backupSoc": 30, "backupEpsBat": 30, "backupFeedIn": 100, "emsSoftwareVersion": "1.0",
+            "dsp1SoftwareVersion": "1.0", "ratedPower": "5000", "region": "AU", "autoStrategy": 0,
+            "chargingList": [], "dischargingList": []
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_diff_and_latest() {
+        assert!(diff(&settings(10, 100), &settings(10, 100)).is_empty());
+        let changes = diff(&settings(10, 100), &settings(20, 100));
+        assert_eq!(
+            changes,
+            vec![SettingsDiff {
+                field: "minCapacity".to_string(),
+                snapshot: json!(10),
+                live: json!(20),
+            }]
+        );
+
+        let snapshot = |name: &str, min_capacity| SettingsSnapshot {
+            name: name.to_string(),
+            timestamp: Local::now(),
+            device_id: "123456".to_string(),
+            settings: settings(min_capacity, 100),
+        };
+        let snapshots = latest(vec![
+            snapshot("a", 10),
+            snapshot("b", 20),
+            snapshot("a", 30),
+        ]);
+        let names: Vec<&str> = snapshots.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["b", "a"]);
+        assert_eq!(snapshots[1].settings.minCapacity, 30);
+
+        assert!(check_name("before-test_1.2").is_ok());
+        assert!(check_name("").is_err());
+        assert!(check_name("a/b").is_err());
+    }
+}
@@ -1,60 +1,613 @@
-use crate::config::AppConfig;
+use crate::audit::{Actor, AuditEntry, AuditEvent};
+use crate::config::{load_config, AppConfig, AuthConfig, Config, HysteresisConfig};
+use crate::controller::{ControllerSettings, PowerController};
 use crate::ecos::client::EcosClient;
-use crate::ecos::data_models::{ChargeModeSettingsRequest, ChargeSchedule};
-use crate::make_struct_with_time_device_info;
+use crate::ecos::data_models::{ChargeModeSettingsRequest, ChargeSchedule, EcosResponse, RunData};
+use crate::end_time::deserialize_end_time;
+use crate::events::ControllerEvent;
+use crate::history::{ModeChange, RunDataSample};
+use crate::metrics::metrics;
+use crate::mqtt::Mqtt;
+use crate::snapshots::SettingsSnapshot;
+use crate::storage::JsonLines;
+use crate::validation::{validate_config, validate_mode, FieldError, ValidationError};
+use chrono::{DateTime, Local, Timelike};
 use rocket::log::private::{info, warn};
 use rocket::serde::{Deserialize, Serialize};
 use rocket::tokio;
-use rocket::tokio::sync::Mutex;
+use rocket::tokio::sync::{broadcast, Mutex, Notify};
 use rocket::tokio::task::JoinHandle;
-use std::sync::Arc;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
 use std::time::{Duration, Instant};
+use utoipa::ToSchema;
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
 #[serde(crate = "rocket::serde", tag = "mode")]
 pub enum ChargeMode {
     #[serde(rename = "conservative")]
     Conservative {
         battery_level: u8,
+        #[serde(default)]
         duration: u64, // in minutes
+        /// When the mode ends, instead of `duration`
+        #[serde(
+            default,
+            deserialize_with = "deserialize_end_time",
+            skip_serializing_if = "Option::is_none"
+        )]
+        until: Option<DateTime<Local>>,
     },
     #[serde(rename = "active")]
     Active {
-        side_load: u32,              // in watts
-        duration: u64,               // in minutes
+        side_load: u32, // in watts
+        #[serde(default)]
+        duration: u64, // in minutes
+        /// When the mode ends, instead of `duration`
+        #[serde(
+            default,
+            deserialize_with = "deserialize_end_time",
+            skip_serializing_if = "Option::is_none"
+        )]
+        until: Option<DateTime<Local>>,
         check_interval: Option<u64>, // in seconds
+        /// An optional controller between the computed and the applied power
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        controller: Option<ControllerSettings>,
     },
     #[serde(rename = "self-sufficient")]
     SelfSufficient { battery_level: u8 },
+    /// Charge from the grid at a fixed power until the battery reaches `target_soc` or the mode ends
+    #[serde(rename = "force-charge")]
+    ForceCharge {
+        target_soc: u8, // in percent
+        power: u32,     // in watts
+        #[serde(default)]
+        duration: u64, // in minutes
+        /// When the mode ends at the latest, instead of `duration`
+        #[serde(
+            default,
+            deserialize_with = "deserialize_end_time",
+            skip_serializing_if = "Option::is_none"
+        )]
+        until: Option<DateTime<Local>>,
+    },
+    /// Discharge to the grid at a fixed power until the battery is down to `floor_soc` or the mode ends
+    #[serde(rename = "force-export")]
+    ForceExport {
+        power: u32,    // in watts
+        floor_soc: u8, // in percent
+        #[serde(default)]
+        duration: u64, // in minutes
+        /// When the mode ends at the latest, instead of `duration`
+        #[serde(
+            default,
+            deserialize_with = "deserialize_end_time",
+            skip_serializing_if = "Option::is_none"
+        )]
+        until: Option<DateTime<Local>>,
+    },
+    /// Keep the export to the grid under `max_export_w` by charging the battery with the surplus
+    #[serde(rename = "export-limit")]
+    ExportLimit {
+        max_export_w: u32, // in watts
+        #[serde(default)]
+        duration: u64, // in minutes
+        /// When the mode ends, instead of `duration`
+        #[serde(
+            default,
+            deserialize_with = "deserialize_end_time",
+            skip_serializing_if = "Option::is_none"
+        )]
+        until: Option<DateTime<Local>>,
+        check_interval: Option<u64>, // in seconds
+        /// An optional controller between the computed and the applied power
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        controller: Option<ControllerSettings>,
+    },
+    /// Discharge the battery to the house when the import from the grid exceeds `import_cap_w`,
+    /// down to `reserve_soc` at the lowest
+    #[serde(rename = "peak-shave")]
+    PeakShave {
+        import_cap_w: u32, // in watts
+        reserve_soc: u8,   // in percent
+        #[serde(default)]
+        duration: u64, // in minutes
+        /// When the mode ends, instead of `duration`
+        #[serde(
+            default,
+            deserialize_with = "deserialize_end_time",
+            skip_serializing_if = "Option::is_none"
+        )]
+        until: Option<DateTime<Local>>,
+        check_interval: Option<u64>, // in seconds, PEAK_SHAVE_INTERVAL by default
+        /// An optional controller between the computed and the applied power
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        controller: Option<ControllerSettings>,
+    },
+    /// Hold the battery at `reserve_soc` or above for the house when the grid fails,
+    /// in the backup mode of the device, until another mode is set
+    #[serde(rename = "backup")]
+    Backup {
+        reserve_soc: u8, // in percent
+    },
+}
+
+impl ChargeMode {
+    /// The names of all modes, as used in the `mode` tag
+    pub const NAMES: [&'static str; 8] = [
+        "conservative",
+        "active",
+        "self-sufficient",
+        "force-charge",
+        "force-export",
+        "export-limit",
+        "peak-shave",
+        "backup",
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ChargeMode::Conservative { .. } => "conservative",
+            ChargeMode::Active { .. } => "active",
+            ChargeMode::SelfSufficient { .. } => "self-sufficient",
+            ChargeMode::ForceCharge { .. } => "force-charge",
+            ChargeMode::ForceExport { .. } => "force-export",
+            ChargeMode::ExportLimit { .. } => "export-limit",
+            ChargeMode::PeakShave { .. } => "peak-shave",
+            ChargeMode::Backup { .. } => "backup",
+        }
+    }
+
+    /// The duration of a timed mode, in minutes
+    pub fn duration(&self) -> Option<u64> {
+        match *self {
+            ChargeMode::Conservative { duration, .. }
+            | ChargeMode::Active { duration, .. }
+            | ChargeMode::ForceCharge { duration, .. }
+            | ChargeMode::ForceExport { duration, .. }
+            | ChargeMode::ExportLimit { duration, .. }
+            | ChargeMode::PeakShave { duration, .. } => Some(duration),
+            ChargeMode::SelfSufficient { .. } | ChargeMode::Backup { .. } => None,
+        }
+    }
+
+    /// The controller settings of a periodic mode
+    pub fn controller(&self) -> Option<&ControllerSettings> {
+        match self {
+            ChargeMode::Active { controller, .. }
+            | ChargeMode::ExportLimit { controller, .. }
+            | ChargeMode::PeakShave { controller, .. } => controller.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// The end of a timed mode, if it was given or resolved
+    pub fn until(&self) -> Option<DateTime<Local>> {
+        match *self {
+            ChargeMode::Conservative { until, .. }
+            | ChargeMode::Active { until, .. }
+            | ChargeMode::ForceCharge { until, .. }
+            | ChargeMode::ForceExport { until, .. }
+            | ChargeMode::ExportLimit { until, .. }
+            | ChargeMode::PeakShave { until, .. } => until,
+            ChargeMode::SelfSufficient { .. } | ChargeMode::Backup { .. } => None,
+        }
+    }
+
+    /// Fill in whichever of `duration` and `until` is missing, counting from `start`.
+    /// `until` takes precedence when both are set; the duration is rounded up to whole minutes.
+    pub fn resolve_end(mut self, start: DateTime<Local>) -> Self {
+        if let ChargeMode::Conservative {
+            duration, until, ..
+        }
+        | ChargeMode::Active {
+            duration, until, ..
+        }
+        | ChargeMode::ForceCharge {
+            duration, until, ..
+        }
+        | ChargeMode::ForceExport {
+            duration, until, ..
+        }
+        | ChargeMode::ExportLimit {
+            duration, until, ..
+        }
+        | ChargeMode::PeakShave {
+            duration, until, ..
+        } = &mut self
+        {
+            match *until {
+                Some(end) => *duration = minutes_until(end, start) as u64,
+                None => *until = Some(start + chrono::Duration::minutes(*duration as i64)),
+            }
+        }
+        self
+    }
+}
+
+/// New values for some parameters of the running mode, for `PATCH /charge-mode`
+#[derive(Debug, Serialize, Deserialize, Clone, Default, ToSchema)]
+#[serde(crate = "rocket::serde", deny_unknown_fields)]
+pub struct ModeAdjustment {
+    pub duration: Option<u64>, // in minutes, from the start of the mode
+    /// A new end of the mode, instead of `duration`
+    #[serde(default, deserialize_with = "deserialize_end_time")]
+    pub until: Option<DateTime<Local>>,
+    pub battery_level: Option<u8>,   // in percent
+    pub side_load: Option<u32>,      // in watts
+    pub check_interval: Option<u64>, // in seconds
+    pub target_soc: Option<u8>,      // in percent
+    pub power: Option<u32>,          // in watts
+    pub floor_soc: Option<u8>,       // in percent
+    pub max_export_w: Option<u32>,   // in watts
+    pub import_cap_w: Option<u32>,   // in watts
+    pub reserve_soc: Option<u8>,     // in percent
+    /// New controller settings, replacing all of the previous ones
+    pub controller: Option<ControllerSettings>,
+}
+
+impl ModeAdjustment {
+    /// The mode with the new values, or an error for each field the mode does not have
+    pub fn apply(&self, charge_mode: &ChargeMode) -> Result<ChargeMode, ValidationError> {
+        let mut charge_mode = charge_mode.clone();
+        let supported: &[&str] = match &mut charge_mode {
+            ChargeMode::Conservative {
+                battery_level,
+                duration,
+                until,
+            } => {
+                *battery_level = self.battery_level.unwrap_or(*battery_level);
+                self.apply_end(duration, until);
+                &["duration", "until", "battery_level"]
+            }
+            ChargeMode::Active {
+                side_load,
+                duration,
+                until,
+                check_interval,
+                controller,
+            } => {
+                *side_load = self.side_load.unwrap_or(*side_load);
+                self.apply_end(duration, until);
+                *check_interval = self.check_interval.or(*check_interval);
+                *controller = self.controller.clone().or(controller.take());
+                &[
+                    "duration",
+                    "until",
+                    "side_load",
+                    "check_interval",
+                    "controller",
+                ]
+            }
+            ChargeMode::SelfSufficient { battery_level } => {
+                *battery_level = self.battery_level.unwrap_or(*battery_level);
+                &["battery_level"]
+            }
+            ChargeMode::Backup { reserve_soc } => {
+                *reserve_soc = self.reserve_soc.unwrap_or(*reserve_soc);
+                &["reserve_soc"]
+            }
+            ChargeMode::ForceCharge {
+                target_soc,
+                power,
+                duration,
+                until,
+            } => {
+                *target_soc = self.target_soc.unwrap_or(*target_soc);
+                *power = self.power.unwrap_or(*power);
+                self.apply_end(duration, until);
+                &["duration", "until", "target_soc", "power"]
+            }
+            ChargeMode::ForceExport {
+                power,
+                floor_soc,
+                duration,
+                until,
+            } => {
+                *power = self.power.unwrap_or(*power);
+                *floor_soc = self.floor_soc.unwrap_or(*floor_soc);
+                self.apply_end(duration, until);
+                &["duration", "until", "power", "floor_soc"]
+            }
+            ChargeMode::ExportLimit {
+                max_export_w,
+                duration,
+                until,
+                check_interval,
+                controller,
+            } => {
+                *max_export_w = self.max_export_w.unwrap_or(*max_export_w);
+                self.apply_end(duration, until);
+                *check_interval = self.check_interval.or(*check_interval);
+                *controller = self.controller.clone().or(controller.take());
+                &[
+                    "duration",
+                    "until",
+                    "max_export_w",
+                    "check_interval",
+                    "controller",
+                ]
+            }
+            ChargeMode::PeakShave {
+                import_cap_w,
+                reserve_soc,
+                duration,
+                until,
+                check_interval,
+                controller,
+            } => {
+                *import_cap_w = self.import_cap_w.unwrap_or(*import_cap_w);
+                *reserve_soc = self.reserve_soc.unwrap_or(*reserve_soc);
+                self.apply_end(duration, until);
+                *check_interval = self.check_interval.or(*check_interval);
+                *controller = self.controller.clone().or(controller.take());
+                &[
+                    "duration",
+                    "until",
+                    "import_cap_w",
+                    "reserve_soc",
+                    "check_interval",
+                    "controller",
+                ]
+            }
+        };
+
+        let errors: Vec<FieldError> = [
+            ("duration", self.duration.is_some()),
+            ("until", self.until.is_some()),
+            ("battery_level", self.battery_level.is_some()),
+            ("side_load", self.side_load.is_some()),
+            ("check_interval", self.check_interval.is_some()),
+            ("target_soc", self.target_soc.is_some()),
+            ("power", self.power.is_some()),
+            ("floor_soc", self.floor_soc.is_some()),
+            ("max_export_w", self.max_export_w.is_some()),
+            ("import_cap_w", self.import_cap_w.is_some()),
+            ("reserve_soc", self.reserve_soc.is_some()),
+            ("controller", self.controller.is_some()),
+        ]
+        .into_iter()
+        .filter(|(field, set)| *set && !supported.contains(field))
+        .map(|(field, _)| FieldError {
+            field: field.to_string(),
+            message: format!("not a parameter of the {} mode", charge_mode.name()),
+        })
+        .collect();
+        if errors.is_empty() {
+            Ok(charge_mode)
+        } else {
+            Err(ValidationError::new(errors))
+        }
+    }
+
+    /// A new `until` or `duration` replaces both, to be resolved again from the start of the mode
+    /// by [`AppState::adjust_mode`]
+    fn apply_end(&self, duration: &mut u64, until: &mut Option<DateTime<Local>>) {
+        if self.until.is_some() {
+            *until = self.until;
+        } else if let Some(new_duration) = self.duration {
+            *duration = new_duration;
+            *until = None;
+        }
+    }
 }
 
 pub struct AppState {
     pub current_mode: Mutex<ChargeMode>,
     pub expiration: Mutex<Option<Instant>>,
     pub background_task: Mutex<Option<JoinHandle<()>>>, // Track the active task
-    pub app_config: AppConfig,
+    /// The `[app]` table of the config, replaced as a whole on reload. Read it with `config()`.
+    pub app_config: RwLock<Arc<AppConfig>>,
     pub ecos_client: Arc<EcosClient>,
+    pub history: JsonLines<RunDataSample>,
+    pub mode_history: JsonLines<ModeChange>,
+    pub audit: JsonLines<AuditEntry>,
+    pub mqtt: Option<Mqtt>,
+    pub events: broadcast::Sender<ControllerEvent>,
+    pub last_run_data: Mutex<Option<RunDataSample>>,
+    pub auth: AuthConfig,
+    pub control: Mutex<ControlStatus>,
+    /// Wakes the background task of a timed mode after [`AppState::adjust_mode`]
+    pub mode_adjusted: Notify,
+    /// Between the computed and the applied power of the periodic modes
+    pub power_controller: Mutex<PowerController>,
+    /// The device settings from before the first mode, with `preserveSettings`
+    pub preserved_settings: Mutex<Option<ChargeModeSettingsRequest>>,
+    pub snapshots: JsonLines<SettingsSnapshot>,
+    /// The config file, to persist settings written through the API
+    pub config_path: Option<PathBuf>,
+}
+
+/// How often modes that stop at a battery SoC check it
+pub const SOC_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+/// How often the peak shave mode checks the import by default, in seconds
+pub const PEAK_SHAVE_INTERVAL: u64 = 60;
+
+/// Why a background task woke up
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Wake {
+    /// The mode has ended
+    Expired,
+    /// The step has passed
+    Step,
+    /// The mode was changed by [`AppState::adjust_mode`]
+    Adjusted,
+}
+
+/// Whole minutes from `now` until `time`, rounded up
+fn minutes_until(time: DateTime<Local>, now: DateTime<Local>) -> i64 {
+    ((time - now).num_seconds().max(0) as u64).div_ceil(60) as i64
+}
+
+/// The monotonic instant of a wall-clock time, now if it has passed
+fn instant_at(time: DateTime<Local>) -> Instant {
+    Instant::now() + (time - Local::now()).to_std().unwrap_or_default()
+}
+
+/// What the controller last did, reported by `GET /status`
+#[derive(Debug, Clone, Default, Serialize, ToSchema)]
+#[serde(crate = "rocket::serde")]
+pub struct ControlStatus {
+    /// When the current mode was set
+    pub started_at: Option<DateTime<Local>>,
+    /// When the current mode ends, for timed modes
+    pub expires_at: Option<DateTime<Local>>,
+    /// The last charge power computed by the control loop, in watts (negative to discharge)
+    pub charge_power: Option<f32>,
+    /// The last settings posted to ECOS successfully
+    pub settings: Option<ChargeModeSettingsRequest>,
+    pub settings_at: Option<DateTime<Local>>,
+    /// The last error of the control loop, kept after later successes
+    pub error: Option<String>,
+    pub error_at: Option<DateTime<Local>>,
 }
 
 impl AppState {
+    /// Create the state in the default self-sufficient mode, with in-memory stores
+    pub fn new(app_config: AppConfig, ecos_client: Arc<EcosClient>) -> Self {
+        AppState {
+            current_mode: Mutex::new(ChargeMode::SelfSufficient {
+                battery_level: app_config.minCapacity as u8,
+            }),
+            expiration: Mutex::new(None),
+            background_task: Mutex::new(None),
+            app_config: RwLock::new(Arc::new(app_config)),
+            ecos_client,
+            history: JsonLines::in_memory(),
+            mode_history: JsonLines::in_memory(),
+            audit: JsonLines::in_memory(),
+            mqtt: None,
+            events: broadcast::channel(64).0,
+            last_run_data: Mutex::new(None),
+            auth: AuthConfig::open(),
+            control: Mutex::new(ControlStatus::default()),
+            mode_adjusted: Notify::new(),
+            power_controller: Mutex::new(PowerController::default()),
+            preserved_settings: Mutex::new(None),
+            snapshots: JsonLines::in_memory(),
+            config_path: None,
+        }
+    }
+
+    /// Push an event to the `/events` subscribers, if there are any
+    pub fn emit(&self, event: ControllerEvent) {
+        let _ = self.events.send(event);
+    }
+
+    /// The `[app]` config as it is now, unchanged by later reloads
+    pub fn config(&self) -> Arc<AppConfig> {
+        self.app_config
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Read the `[app]` table of the config file again and switch to it if it is valid.
+    /// Running modes use it from their next check; other tables need a restart.
+    pub async fn reload_config(&self, actor: Actor) -> Result<(), ValidationError> {
+        let result = self.load_app_config().and_then(|app_config| {
+            validate_config(&app_config)?;
+            *self.app_config.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(app_config);
+            Ok(())
+        });
+        match &result {
+            Ok(()) => info!(target: "app", "Config reloaded"),
+            Err(e) => warn!("Config not reloaded: {:?}", e.errors),
+        }
+        let error = result.as_ref().err().map(|e| {
+            e.errors
+                .iter()
+                .map(|e| format!("{}: {}", e.field, e.message))
+                .collect::<Vec<_>>()
+                .join(", ")
+        });
+        self.record_audit(actor, AuditEvent::ConfigReload { error })
+            .await;
+        result
+    }
+
+    fn load_app_config(&self) -> Result<AppConfig, ValidationError> {
+        let file_error = |message: String| {
+            ValidationError::config(vec![FieldError {
+                field: "file".to_string(),
+                message,
+            }])
+        };
+        let path = self
+            .config_path
+            .as_ref()
+            .ok_or_else(|| file_error("no config file".to_string()))?;
+        load_config::<Config>(path)
+            .map(|config| config.app)
+            .map_err(|e| file_error(e.to_string()))
+    }
+
+    /// Append an entry to the audit log
+    pub async fn record_audit(&self, actor: Actor, event: AuditEvent) {
+        let entry = AuditEntry {
+            timestamp: Local::now(),
+            actor,
+            event,
+        };
+        if let Err(e) = self.audit.append(&entry).await {
+            warn!("Failed to write audit log: {:?}", e);
+        }
+    }
+
+    /// Record a mode change in the mode history and audit log, and push it to the `/events` subscribers
+    async fn record_mode(&self, charge_mode: &ChargeMode, actor: Actor) -> ModeChange {
+        let change = ModeChange {
+            timestamp: Local::now(),
+            mode: charge_mode.clone(),
+        };
+        if let Err(e) = self.mode_history.append(&change).await {
+            warn!("Failed to record charge mode change: {:?}", e);
+        }
+        self.record_audit(
+            actor,
+            AuditEvent::ModeChange {
+                mode: charge_mode.clone(),
+            },
+        )
+        .await;
+        self.emit(ControllerEvent::ModeChange {
+            timestamp: change.timestamp,
+            mode: charge_mode.clone(),
+        });
+        change
+    }
+
     /// update the current charge mode and expiration time
-    pub async fn update_mode(&self, charge_mode: ChargeMode) {
+    pub async fn update_mode(&self, charge_mode: ChargeMode, actor: Actor) {
         let mut current_mode = self.current_mode.lock().await;
         let mut expiration = self.expiration.lock().await;
 
+        let now = Local::now();
+        let charge_mode = charge_mode.resolve_end(now);
+        self.record_mode(&charge_mode, actor).await;
+
+        *expiration = charge_mode.until().map(instant_at);
+        let mut control = self.control.lock().await;
+        control.started_at = Some(now);
+        control.expires_at = charge_mode.until();
+        *self.power_controller.lock().await =
+            PowerController::new(charge_mode.controller().cloned());
+
         *current_mode = charge_mode;
+    }
 
-        match *current_mode {
-            ChargeMode::Conservative { duration, .. } => {
-                *expiration = Some(Instant::now() + Duration::from_secs(duration * 60));
-            }
-            ChargeMode::Active { duration, .. } => {
-                *expiration = Some(Instant::now() + Duration::from_secs(duration * 60));
-            }
-            _ => {
-                *expiration = None;
-            }
-        }
+    /// Record an error of the control loop for `GET /status`
+    pub async fn record_error(&self, error: String) {
+        let mut control = self.control.lock().await;
+        control.error = Some(error);
+        control.error_at = Some(Local::now());
+    }
+
+    /// Switch to a new charge mode and start its background task
+    pub async fn apply_mode(state: &Arc<AppState>, charge_mode: ChargeMode, actor: Actor) {
+        state.update_mode(charge_mode, actor).await;
+        AppState::start_task(state).await;
     }
 
     /// Cancel the current background task if it exists
@@ -66,16 +619,64 @@ impl AppState {
     }
 
     /// Reset to default charge mode
-    pub async fn reset_mode(&self) {
+    pub async fn reset_mode(&self, actor: Actor) {
         info!(target: "app", "Resetting to default charge mode");
 
-        self.update_mode(ChargeMode::SelfSufficient {
-            battery_level: self.app_config.minCapacity as u8,
-        })
+        self.update_mode(
+            ChargeMode::SelfSufficient {
+                battery_level: self.config().minCapacity as u8,
+            },
+            actor,
+        )
         .await;
+        self.restore_settings(self.config().minCapacity).await;
+    }
 
-        self.update_charge_mode(0, Some(self.app_config.minCapacity), None, None)
-            .await;
+    /// Write back the preserved device settings and forget them, so the next mode preserves the settings
+    /// of that time. Without preserved settings, write the self-sufficient settings for `battery_level`.
+    async fn restore_settings(&self, battery_level: i32) {
+        let preserved = self.preserved_settings.lock().await.take();
+        match preserved {
+            Some(mut request) => {
+                info!(target: "app", "Restoring the settings from before the mode");
+                request._t = EcosClient::get_epoch_time();
+                self.write_settings(request).await;
+            }
+            None => {
+                self.update_charge_mode(0, Some(battery_level), None, None)
+                    .await
+            }
+        }
+    }
+
+    /// With `preserveSettings`, keep the device settings from before the first mode to build on and restore.
+    /// A mode that follows another one keeps the settings from before the first.
+    async fn preserve_settings(&self) {
+        if !self.config().preserveSettings {
+            return;
+        }
+        let mut preserved = self.preserved_settings.lock().await;
+        if preserved.is_some() {
+            return;
+        }
+        let device_id = &self.config().deviceId;
+        match self
+            .ecos_client
+            .get_charge_mode_settings_fresh(device_id)
+            .await
+        {
+            Ok(res) => {
+                *preserved = Some(ChargeModeSettingsRequest::from_settings(
+                    device_id.clone(),
+                    &res.data,
+                ));
+            }
+            Err(e) => {
+                warn!("Failed to read the settings to preserve: {:?}", e);
+                self.record_error(format!("Failed to read the settings to preserve: {}", e))
+                    .await;
+            }
+        }
     }
 
     pub async fn update_charge_mode(
@@ -86,44 +687,214 @@ impl AppState {
         check_interval: Option<u64>,
     ) {
         let charge_power = if charge_use_mode == 1 {
-            self.compute_charge_power(side_load.unwrap_or(0))
-                .await
-                .unwrap_or(0.0)
+            match self.compute_charge_power(side_load.unwrap_or(0)).await {
+                Ok(charge_power) => charge_power,
+                Err(e) => {
+                    warn!("Failed to compute charge power: {:?}", e);
+                    self.record_error(format!("Failed to compute charge power: {}", e))
+                        .await;
+                    0.0
+                }
+            }
         } else {
             0.0
         };
+        let check_interval = check_interval.unwrap_or(self.config().checkInterval);
         let charging_list = if charge_power.abs() > 0.0 {
-            let check_interval = check_interval.unwrap_or(self.app_config.checkInterval);
             info!(target: "app", "Charge/Discharge power: {} W", charge_power);
             vec![ChargeSchedule::from_now(
-                (check_interval / 60) as i64,
+                self.window_minutes(check_interval),
                 charge_power.abs() as i32,
             )]
         } else {
             vec![]
         };
 
+        let mut request = self.settings_request(charge_use_mode, battery_level).await;
+        if charge_power > 0.0 {
+            request.chargingList = charging_list;
+        } else if charge_power < 0.0 {
+            request.dischargeToGridFlag = 1;
+            request.dischargingList = charging_list;
+        }
+        self.write_periodic_settings(request, check_interval).await;
+    }
+
+    /// Charge the battery with the export over `max_export_w` until the next check.
+    /// With `ratedPower` configured, `maxFeedIn` caps the export too, for when the battery cannot take more.
+    pub async fn update_export_limit(&self, max_export_w: u32, check_interval: u64) {
+        let charge_power = match self.compute_export_limit_power(max_export_w).await {
+            Ok(charge_power) => charge_power,
+            Err(e) => {
+                warn!("Failed to compute charge power: {:?}", e);
+                self.record_error(format!("Failed to compute charge power: {}", e))
+                    .await;
+                0.0
+            }
+        };
+        let mut request = self.settings_request(1, None).await;
+        if charge_power > 0.0 {
+            request.chargingList = vec![ChargeSchedule::from_now(
+                self.window_minutes(check_interval),
+                charge_power as i32,
+            )];
+        }
+        if let Some(rated_power) = self.config().ratedPower {
+            request.maxFeedIn = request
+                .maxFeedIn
+                .min(feed_in_percent(max_export_w, rated_power));
+        }
+        self.write_periodic_settings(request, check_interval).await;
+    }
+
+    /// Discharge the battery by the import over `import_cap_w` until the next check, keeping `reserve_soc`.
+    /// The window lasts a minute longer than the check, so that the battery does not stop in between.
+    pub async fn update_peak_shave(&self, import_cap_w: u32, reserve_soc: u8, check_interval: u64) {
+        let discharge_power = match self
+            .compute_peak_shave_power(import_cap_w, reserve_soc)
+            .await
+        {
+            Ok(discharge_power) => discharge_power,
+            Err(e) => {
+                warn!("Failed to compute discharge power: {:?}", e);
+                self.record_error(format!("Failed to compute discharge power: {}", e))
+                    .await;
+                0.0
+            }
+        };
+        let mut request = self.settings_request(1, Some(reserve_soc as i32)).await;
+        if discharge_power > 0.0 {
+            request.dischargingList = vec![ChargeSchedule::from_now(
+                self.window_minutes(check_interval) + 1,
+                discharge_power as i32,
+            )];
+        }
+        self.write_periodic_settings(request, check_interval).await;
+    }
+
+    /// The backup mode of the device (`chargeUseMode` 2) with `reserve_soc` for both the battery and EPS minimum.
+    /// The device keeps them as `backupSoc` and `backupEpsBat` in its settings.
+    pub async fn backup_settings(&self, reserve_soc: u8) -> ChargeModeSettingsRequest {
+        let mut request = self.settings_request(2, Some(reserve_soc as i32)).await;
+        request.epsBatteryMin = reserve_soc as i32;
+        request
+    }
+
+    /// The settings of the default mode, for a mode to change what it needs: from the config,
+    /// or the preserved device settings. With the latter, modes on the time-of-use schedule (`chargeUseMode` 1)
+    /// own both windows lists.
+    pub async fn settings_request(
+        &self,
+        charge_use_mode: i32,
+        battery_level: Option<i32>,
+    ) -> ChargeModeSettingsRequest {
+        if let Some(preserved) = &*self.preserved_settings.lock().await {
+            let mut request = preserved.clone();
+            request._t = EcosClient::get_epoch_time();
+            request.chargeUseMode = charge_use_mode;
+            request.minCapacity = battery_level.unwrap_or(preserved.minCapacity);
+            if charge_use_mode == 1 {
+                request.chargingList = vec![];
+                request.dischargingList = vec![];
+            }
+            return request;
+        }
+        let config = self.config();
+        let mut request = config.settings_request();
+        request.chargeUseMode = charge_use_mode;
+        request.minCapacity = battery_level.unwrap_or(config.minCapacity);
+        request
+    }
+
+    /// Post settings to ECOS, recording them for `GET /status` and in the audit log
+    pub async fn write_settings(&self, request: ChargeModeSettingsRequest) {
+        let _ = self
+            .write_settings_as(request, Actor::controller("apply charge mode"))
+            .await;
+    }
+
+    /// Post settings to ECOS on behalf of `actor`, recording them for `GET /status` and in the audit log
+    pub async fn write_settings_as(
+        &self,
+        request: ChargeModeSettingsRequest,
+        actor: Actor,
+    ) -> Result<EcosResponse, String> {
         let res = self
             .ecos_client
-            .post_charge_mode_settings(make_struct_with_time_device_info!(
-                ChargeModeSettingsRequest,
-                deviceId: self.app_config.deviceId.clone(),
-                chargeUseMode: charge_use_mode,
-                minCapacity: battery_level.unwrap_or(self.app_config.minCapacity),
-                maxFeedIn: self.app_config.maxFeedIn,
-                dischargeToGridFlag: if charge_power < 0.0 { 1 } else { self.app_config.dischargeToGridFlag },
-                chargingList: if charge_power > 0.0 { charging_list.clone() } else { self.app_config.chargingList.clone() },
-                dischargingList: if charge_power < 0.0 { charging_list } else { self.app_config.dischargingList.clone() },
-                epsBatteryMin: self.app_config.epsBatteryMin
-            ))
-            .await;
-        if let Err(e) = res {
-            warn!("Failed to update charge mode: {:?}", e);
+            .post_charge_mode_settings(request.clone())
+            .await
+            .map_err(|e| e.to_string());
+        match &res {
+            Ok(_) => {
+                let mut control = self.control.lock().await;
+                control.settings = Some(request.clone());
+                control.settings_at = Some(Local::now());
+            }
+            Err(e) => {
+                warn!("Failed to update charge mode: {:?}", e);
+                self.record_error(format!("Failed to update charge mode: {}", e))
+                    .await;
+            }
+        }
+
+        let mode = self.current_mode.lock().await.clone();
+        let (response, error) = match &res {
+            Ok(response) => (Some(response.clone()), None),
+            Err(e) => (None, Some(e.clone())),
+        };
+        self.record_audit(
+            actor,
+            AuditEvent::SettingsWrite {
+                mode,
+                request: Box::new(request),
+                response,
+                error,
+            },
+        )
+        .await;
+        res
+    }
+
+    /// How long the schedule window of a periodic mode lasts, in minutes: until the next check,
+    /// and for the hold time of the hysteresis on top when it is enabled
+    fn window_minutes(&self, check_interval: u64) -> i64 {
+        ((check_interval + self.config().hysteresis.window_hold()) / 60) as i64
+    }
+
+    /// Post settings of a periodic mode, unless they are within the hysteresis of the last ones
+    /// posted in the same mode. They are posted anyway before the last windows would end ahead of the next check.
+    pub async fn write_periodic_settings(
+        &self,
+        request: ChargeModeSettingsRequest,
+        check_interval: u64,
+    ) {
+        let skip = {
+            let control = self.control.lock().await;
+            let next_check = Local::now() + chrono::Duration::seconds(check_interval as i64);
+            match (&control.settings, control.settings_at, control.started_at) {
+                (Some(last), Some(last_at), Some(started_at)) if last_at >= started_at => {
+                    within_hysteresis(
+                        last,
+                        last_at,
+                        &request,
+                        next_check,
+                        &self.config().hysteresis,
+                    )
+                }
+                _ => false,
+            }
+        };
+        if skip {
+            info!(target: "app", "Settings within the hysteresis, not written");
+            metrics().settings_writes_skipped.inc();
             return;
         }
+        self.write_settings(request).await;
     }
 
-    /// Start a background task to reset the charge mode
+    /// Start a background task to reset the charge mode.
+    /// Timed modes read their parameters and expiration from the state on every step,
+    /// so [`AppState::adjust_mode`] can change them while the task runs.
     pub async fn start_task(state: &Arc<AppState>) {
         state.cancel_task().await;
 
@@ -131,44 +902,103 @@ impl AppState {
         let task = tokio::spawn(async move {
             // release the lock immediately after cloning
             let current_mode = state_clone.current_mode.lock().await.clone();
+            // self-sufficient never ends, so it has nothing to restore later
+            if !matches!(current_mode, ChargeMode::SelfSufficient { .. }) {
+                state_clone.preserve_settings().await;
+            }
             match current_mode {
                 ChargeMode::SelfSufficient { battery_level } => {
                     info!(target: "app", "Self-sufficient mode: {}%", battery_level);
-                    state_clone
-                        .update_charge_mode(0, Some(battery_level as i32), None, None)
-                        .await;
+                    state_clone.restore_settings(battery_level as i32).await;
+                }
+                ChargeMode::Backup { reserve_soc } => {
+                    info!(target: "app", "Backup mode: {}% reserve", reserve_soc);
+                    let request = state_clone.backup_settings(reserve_soc).await;
+                    state_clone.write_settings(request).await;
                 }
                 ChargeMode::Conservative {
                     duration,
                     battery_level,
+                    ..
                 } => {
                     info!(target: "app", "Conservative mode: {}%, {} mins", battery_level, duration);
-                    state_clone
-                        .update_charge_mode(0, Some(battery_level as i32), None, None)
-                        .await;
-                    tokio::time::sleep(Duration::from_secs(duration * 60)).await;
+                    loop {
+                        let ChargeMode::Conservative { battery_level, .. } =
+                            *state_clone.current_mode.lock().await
+                        else {
+                            // another mode replaced this one, so it must not be reset
+                            return;
+                        };
+                        state_clone
+                            .update_charge_mode(0, Some(battery_level as i32), None, None)
+                            .await;
+                        if state_clone.wait_until_expired(None).await == Wake::Expired {
+                            break;
+                        }
+                    }
                     info!(target: "app", "Conservative mode expired");
-                    state_clone.reset_mode().await;
+                    state_clone.expire_mode("conservative mode expired").await;
                 }
                 ChargeMode::Active {
                     duration,
                     side_load,
-                    check_interval,
+                    ..
                 } => {
                     info!(target: "app", "Active mode: side-load {} W, {} mins", side_load, duration);
-                    let now = Instant::now();
-                    let expiration = now + Duration::from_secs(duration * 60);
-                    while Instant::now() < expiration {
+                    loop {
+                        let ChargeMode::Active {
+                            side_load,
+                            check_interval,
+                            ..
+                        } = *state_clone.current_mode.lock().await
+                        else {
+                            return;
+                        };
                         state_clone
                             .update_charge_mode(1, None, Some(side_load), check_interval)
                             .await;
-                        tokio::time::sleep(Duration::from_secs(
-                            check_interval.unwrap_or(state_clone.app_config.checkInterval),
-                        ))
-                        .await;
-                        info!(target: "app", "Active mode: {} min left", expiration.duration_since(Instant::now()).as_secs() / 60);
+                        let check_interval = Duration::from_secs(
+                            check_interval.unwrap_or(state_clone.config().checkInterval),
+                        );
+                        if state_clone.wait_until_expired(Some(check_interval)).await
+                            == Wake::Expired
+                        {
+                            break;
+                        }
+                        if let Some(expiration) = *state_clone.expiration.lock().await {
+                            info!(target: "app", "Active mode: {} min left", expiration.saturating_duration_since(Instant::now()).as_secs() / 60);
+                        }
                     }
-                    state_clone.reset_mode().await;
+                    state_clone.expire_mode("active mode expired").await;
+                }
+                ChargeMode::ForceCharge {
+                    target_soc, power, ..
+                } => {
+                    info!(target: "app", "Force charge mode: {} W up to {}%", power, target_soc);
+                    state_clone.run_to_soc().await;
+                }
+                ChargeMode::ForceExport {
+                    power, floor_soc, ..
+                } => {
+                    info!(target: "app", "Force export mode: {} W down to {}%", power, floor_soc);
+                    state_clone.run_to_soc().await;
+                }
+                ChargeMode::ExportLimit {
+                    max_export_w,
+                    duration,
+                    ..
+                } => {
+                    info!(target: "app", "Export limit mode: {} W, {} mins", max_export_w, duration);
+                    state_clone.run_grid_limit().await;
+                }
+                ChargeMode::PeakShave {
+                    import_cap_w,
+                    reserve_soc,
+                    duration,
+                    ..
+                } => {
+                    info!(target: "app", "Peak shave mode: {} W import cap, {}% reserve, {} mins", import_cap_w, reserve_soc, duration);
+                    state_clone.run_grid_limit().await;
                 }
             }
         });
@@ -176,6 +1006,205 @@ impl AppState {
         *state.background_task.lock().await = Some(task);
     }
 
+    /// Run a mode that follows the grid power every check interval: export limit or peak shave
+    async fn run_grid_limit(&self) {
+        loop {
+            let mode = self.current_mode.lock().await.clone();
+            let check_interval = match mode {
+                ChargeMode::ExportLimit {
+                    max_export_w,
+                    check_interval,
+                    ..
+                } => {
+                    let check_interval = check_interval.unwrap_or(self.config().checkInterval);
+                    self.update_export_limit(max_export_w, check_interval).await;
+                    check_interval
+                }
+                ChargeMode::PeakShave {
+                    import_cap_w,
+                    reserve_soc,
+                    check_interval,
+                    ..
+                } => {
+                    let check_interval = check_interval.unwrap_or(PEAK_SHAVE_INTERVAL);
+                    self.update_peak_shave(import_cap_w, reserve_soc, check_interval)
+                        .await;
+                    check_interval
+                }
+                _ => return,
+            };
+            if self
+                .wait_until_expired(Some(Duration::from_secs(check_interval)))
+                .await
+                == Wake::Expired
+            {
+                break;
+            }
+        }
+        let mode = self.current_mode.lock().await.name();
+        info!(target: "app", "{} mode expired", mode);
+        self.expire_mode(&format!("{} mode expired", mode)).await;
+    }
+
+    /// Sleep until the mode expires, at most for `step`, or until the mode is adjusted
+    async fn wait_until_expired(&self, step: Option<Duration>) -> Wake {
+        let Some(expiration) = *self.expiration.lock().await else {
+            return Wake::Expired;
+        };
+        let now = Instant::now();
+        if now >= expiration {
+            return Wake::Expired;
+        }
+        let wake = step.map_or(expiration, |step| (now + step).min(expiration));
+        tokio::select! {
+            _ = tokio::time::sleep_until(wake.into()) => {}
+            _ = self.mode_adjusted.notified() => return Wake::Adjusted,
+        }
+        // the expiration may have been moved while sleeping
+        let expired = self
+            .expiration
+            .lock()
+            .await
+            .is_none_or(|expiration| Instant::now() >= expiration);
+        if expired {
+            Wake::Expired
+        } else {
+            Wake::Step
+        }
+    }
+
+    /// The battery SoC from the run data, or None if it cannot be read
+    async fn battery_soc(&self) -> Option<f32> {
+        match self.control_run_data().await {
+            Ok(run_data) => run_data.map(|run_data| run_data.batterySoc),
+            Err(e) => {
+                warn!("Failed to read the battery SOC: {:?}", e);
+                self.record_error(format!("Failed to read the battery SOC: {}", e))
+                    .await;
+                None
+            }
+        }
+    }
+
+    /// Run a mode with a fixed power window that ends at a battery SoC: force charge or force export.
+    /// The window is written once, and again after an adjustment.
+    async fn run_to_soc(&self) {
+        let mut apply = true;
+        loop {
+            let mode = self.current_mode.lock().await.clone();
+            let soc = self.battery_soc().await;
+            let (reached, reason) = match mode {
+                ChargeMode::ForceCharge { target_soc, .. } => (
+                    soc.is_some_and(|soc| soc >= target_soc as f32),
+                    "force charge target reached",
+                ),
+                ChargeMode::ForceExport { floor_soc, .. } => (
+                    soc.is_some_and(|soc| soc <= floor_soc as f32),
+                    "force export floor reached",
+                ),
+                _ => return,
+            };
+            if reached {
+                info!(target: "app", "{} mode: SoC {:?}% reached the limit", mode.name(), soc);
+                self.expire_mode(reason).await;
+                return;
+            }
+            if apply {
+                self.write_settings(self.window_settings(&mode).await).await;
+            }
+            match self.wait_until_expired(Some(SOC_CHECK_INTERVAL)).await {
+                Wake::Expired => break,
+                Wake::Step => apply = false,
+                Wake::Adjusted => apply = true,
+            }
+        }
+        let mode = self.current_mode.lock().await.name();
+        info!(target: "app", "{} mode expired", mode);
+        self.expire_mode(&format!("{} mode expired", mode)).await;
+    }
+
+    /// The settings of a fixed power charge or discharge window until the end of the mode
+    async fn window_settings(&self, mode: &ChargeMode) -> ChargeModeSettingsRequest {
+        // a window that ends before the next SoC check would stop the mode's charging or export early
+        let minutes = mode
+            .until()
+            .map(|until| minutes_until(until, Local::now()))
+            .or(mode.duration().map(|duration| duration as i64))
+            .unwrap_or(0)
+            .max(1);
+        match *mode {
+            ChargeMode::ForceCharge { power, .. } => {
+                let mut request = self.settings_request(1, None).await;
+                request.chargingList = vec![ChargeSchedule::from_now(minutes, power as i32)];
+                request
+            }
+            ChargeMode::ForceExport {
+                power, floor_soc, ..
+            } => {
+                let mut request = self.settings_request(1, Some(floor_soc as i32)).await;
+                request.dischargeToGridFlag = 1;
+                request.dischargingList = vec![ChargeSchedule::from_now(minutes, power as i32)];
+                request
+            }
+            _ => self.settings_request(0, None).await,
+        }
+    }
+
+    async fn expire_mode(&self, reason: &str) {
+        let mode = self.current_mode.lock().await.clone();
+        self.emit(ControllerEvent::ModeExpired {
+            timestamp: Local::now(),
+            mode,
+        });
+        self.reset_mode(Actor::controller(reason)).await;
+    }
+
+    /// Change the parameters of the running mode in place, keeping its start time and background task.
+    /// A new `duration` counts from when the mode started, and a new `until` replaces it.
+    /// The current mode stays locked throughout, so a mode set in the meantime is not overwritten.
+    pub async fn adjust_mode(
+        state: &Arc<AppState>,
+        adjustment: ModeAdjustment,
+        actor: Actor,
+    ) -> Result<ChargeMode, ValidationError> {
+        let mut current_mode = state.current_mode.lock().await;
+        let charge_mode = adjustment.apply(&current_mode)?;
+        validate_mode(&charge_mode, &state.config())?;
+        let started_at = state
+            .control
+            .lock()
+            .await
+            .started_at
+            .unwrap_or_else(Local::now);
+        let charge_mode = charge_mode.resolve_end(started_at);
+
+        state.record_mode(&charge_mode, actor).await;
+        if let Some(until) = charge_mode.until() {
+            *state.expiration.lock().await = Some(instant_at(until));
+            state.control.lock().await.expires_at = Some(until);
+        }
+        *current_mode = charge_mode.clone();
+        state
+            .power_controller
+            .lock()
+            .await
+            .set_settings(charge_mode.controller().cloned());
+
+        let running = state
+            .background_task
+            .lock()
+            .await
+            .as_ref()
+            .is_some_and(|task| !task.is_finished());
+        if running && charge_mode.duration().is_some() {
+            state.mode_adjusted.notify_one();
+        } else {
+            // the new task reads the mode once the lock is released
+            AppState::start_task(state).await;
+        }
+        Ok(charge_mode)
+    }
+
     /// Compute the charge power based on the current state
     /// This is a simplified implementation that only works for my home configuration
     /// where I have two identical PV inverters and one of them is connected to the battery.
@@ -187,17 +1216,11 @@ impl AppState {
     ) -> Result<f32, Box<dyn std::error::Error + Send + Sync>> {
         // the implementation only works for my home configuration where
         // I have two identical PV inverters and one of them is connected to the battery
-        let run_data = self
-            .ecos_client
-            .get_run_data(self.app_config.deviceId.clone())
-            .await?;
-        if run_data.data.batterySoc < 0.01 {
-            // NOTE: the server is returning null data. We do not want to modify the charging behavior.
-            warn!("Battery SOC is too low (potentially disconnected from the server)");
+        let Some(run_data) = self.control_run_data().await? else {
             return Ok(0.0);
-        }
-        let total_pv = run_data.data.solarPower * 2.0;
-        let total_load = run_data.data.homePower + run_data.data.epsPower + side_load as f32;
+        };
+        let total_pv = run_data.solarPower * 2.0;
+        let total_load = run_data.homePower + run_data.epsPower + side_load as f32;
         let net_power = total_pv - total_load;
         // if net_power is positive, it is a charge power (to the battery)
         // otherwise, it is a discharge power, which we need to subtract PV power from net power
@@ -205,12 +1228,138 @@ impl AppState {
         let charge_power = if net_power > 0.0 {
             net_power
         } else {
-            net_power - run_data.data.solarPower
+            net_power - run_data.solarPower
+        };
+        let computed = charge_power.clamp(-5000.0, 5000.0);
+        let charge_power = self.power_controller.lock().await.update(computed);
+        self.record_charge_power(charge_power).await;
+
+        info!(target: "app", "Total PV: {} W, Total Load: {} W, Net Power: {} W, Computed: {} W, Charge Power: {} W", total_pv, total_load, net_power, computed, charge_power);
+
+        Ok(charge_power)
+    }
+
+    /// The charge power that keeps the export under `max_export_w`, on top of what the battery takes already.
+    pub async fn compute_export_limit_power(
+        &self,
+        max_export_w: u32,
+    ) -> Result<f32, Box<dyn std::error::Error + Send + Sync>> {
+        let Some(run_data) = self.control_run_data().await? else {
+            return Ok(0.0);
         };
-        let charge_power = charge_power.clamp(-5000.0, 5000.0);
+        let export = -grid_power(&run_data);
+        let battery_charge = run_data.batteryPower.max(0.0);
+        let computed = (battery_charge + export - max_export_w as f32).clamp(0.0, 5000.0);
+        let charge_power = self.power_controller.lock().await.update(computed).max(0.0);
+        self.record_charge_power(charge_power).await;
 
-        info!(target: "app", "Total PV: {} W, Total Load: {} W, Net Power: {} W, Charge Power: {} W", total_pv, total_load, net_power, charge_power);
+        info!(target: "app", "Export: {} W, Limit: {} W, Battery: {} W, Computed: {} W, Charge Power: {} W", export, max_export_w, battery_charge, computed, charge_power);
 
         Ok(charge_power)
     }
+
+    /// The discharge power that brings the import down to `import_cap_w`, on top of what the battery gives already.
+    /// Never more than the house takes, so that the battery does not export, and nothing at `reserve_soc`.
+    pub async fn compute_peak_shave_power(
+        &self,
+        import_cap_w: u32,
+        reserve_soc: u8,
+    ) -> Result<f32, Box<dyn std::error::Error + Send + Sync>> {
+        let Some(run_data) = self.control_run_data().await? else {
+            return Ok(0.0);
+        };
+        let import = grid_power(&run_data);
+        let load = run_data.homePower + run_data.epsPower;
+        let battery_discharge = (-run_data.batteryPower).max(0.0);
+        let computed = if run_data.batterySoc <= reserve_soc as f32 {
+            0.0
+        } else {
+            (battery_discharge + import - import_cap_w as f32).clamp(0.0, load.clamp(0.0, 5000.0))
+        };
+        // the controller works on the charge power, which is negative when discharging
+        let discharge_power = (-self.power_controller.lock().await.update(-computed)).max(0.0);
+        self.record_charge_power(-discharge_power).await;
+
+        info!(target: "app", "Import: {} W, Cap: {} W, Load: {} W, Battery: {} W, Computed: {} W, Discharge Power: {} W", import, import_cap_w, load, battery_discharge, computed, discharge_power);
+
+        Ok(discharge_power)
+    }
+
+    /// The run data for a control step, or `None` while the server returns null data
+    async fn control_run_data(
+        &self,
+    ) -> Result<Option<RunData>, Box<dyn std::error::Error + Send + Sync>> {
+        let run_data = self
+            .ecos_client
+            .get_run_data(self.config().deviceId.clone())
+            .await?
+            .data;
+        if run_data.batterySoc < 0.01 {
+            // NOTE: the server is returning null data. We do not want to modify the charging behavior.
+            warn!("Battery SOC is too low (potentially disconnected from the server)");
+            return Ok(None);
+        }
+        Ok(Some(run_data))
+    }
+
+    async fn record_charge_power(&self, charge_power: f32) {
+        metrics().charge_power.set(charge_power as f64);
+        self.control.lock().await.charge_power = Some(charge_power);
+        self.emit(ControllerEvent::ChargePower {
+            timestamp: Local::now(),
+            power: charge_power,
+        });
+    }
+}
+
+/// Whether `request` differs from the `last` settings posted at `last_at` by less than the hysteresis,
+/// and the windows of the last settings last beyond `next_check` (with a minute to spare)
+fn within_hysteresis(
+    last: &ChargeModeSettingsRequest,
+    last_at: DateTime<Local>,
+    request: &ChargeModeSettingsRequest,
+    next_check: DateTime<Local>,
+    hysteresis: &HysteresisConfig,
+) -> bool {
+    if hysteresis.power == 0
+        || last.deviceId != request.deviceId
+        || last.chargeUseMode != request.chargeUseMode
+        || last.minCapacity != request.minCapacity
+        || last.maxFeedIn != request.maxFeedIn
+        || last.dischargeToGridFlag != request.dischargeToGridFlag
+        || last.epsBatteryMin != request.epsBatteryMin
+        || last.chargingList.len() != request.chargingList.len()
+        || last.dischargingList.len() != request.dischargingList.len()
+    {
+        return false;
+    }
+    let last_windows = last.chargingList.iter().chain(&last.dischargingList);
+    let windows = request.chargingList.iter().chain(&request.dischargingList);
+    last_windows.zip(windows).all(|(last_window, window)| {
+        last_window.power.abs_diff(window.power) < hysteresis.power
+            && window_end(last_window, last_at) > next_check + chrono::Duration::minutes(1)
+    })
+}
+
+/// When a window that was posted at `posted_at` ends, within a day of it
+fn window_end(window: &ChargeSchedule, posted_at: DateTime<Local>) -> DateTime<Local> {
+    let posted = (posted_at.hour() * 60 + posted_at.minute()) as i64;
+    let end = (window.endHour * 60 + window.endMinute) as i64;
+    posted_at - chrono::Duration::seconds(posted_at.second() as i64)
+        + chrono::Duration::minutes((end - posted).rem_euclid(24 * 60))
+}
+
+/// The grid power from the meter, or from the inverter without a meter (`meterPower` is 0).
+/// Positive when importing, negative when exporting.
+pub(crate) fn grid_power(run_data: &RunData) -> f32 {
+    if run_data.meterPower != 0.0 {
+        run_data.meterPower
+    } else {
+        run_data.gridPower
+    }
+}
+
+/// `maxFeedIn` is a percentage of the rated power of the inverter, rounded down to stay under the limit
+fn feed_in_percent(max_export_w: u32, rated_power: u32) -> i32 {
+    (max_export_w as u64 * 100 / rated_power.max(1) as u64).min(100) as i32
 }
@@ -0,0 +1,198 @@
+use chrono::{DateTime, Datelike, Local, NaiveDate, TimeDelta};
+use rocket::log::private::warn;
+use rocket::serde::de::DeserializeOwned;
+use rocket::serde::json::serde_json;
+use rocket::serde::Serialize;
+use rocket::tokio::fs::{self, OpenOptions};
+use rocket::tokio::io::AsyncWriteExt;
+use rocket::tokio::sync::Mutex;
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+
+/// Records that can be stored in a [`JsonLines`] store and queried by time.
+pub trait Timestamped {
+    fn timestamp(&self) -> DateTime<Local>;
+}
+
+/// An append-only store of JSON records, one per line.
+/// Without a path the records are only kept in memory, which is what the tests use.
+pub struct JsonLines<T> {
+    path: Option<PathBuf>,
+    /// Splits the file by the month of each record, see [`JsonLines::open_monthly`]
+    partition: Option<fn(&T) -> DateTime<Local>>,
+    memory: Mutex<Vec<String>>,
+    _record: PhantomData<fn() -> T>,
+}
+
+impl<T: Serialize + DeserializeOwned> JsonLines<T> {
+    pub fn open(path: impl Into<PathBuf>) -> Self {
+        JsonLines {
+            path: Some(path.into()),
+            partition: None,
+            memory: Mutex::new(vec![]),
+            _record: PhantomData,
+        }
+    }
+
+    pub fn in_memory() -> Self {
+        JsonLines {
+            path: None,
+            partition: None,
+            memory: Mutex::new(vec![]),
+            _record: PhantomData,
+        }
+    }
+
+    pub async fn append(&self, record: &T) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let line = serde_json::to_string(record)?;
+        // the lock also serialises writers to the file
+        let mut memory = self.memory.lock().await;
+        match &self.path {
+            Some(path) => {
+                let path = match self.partition {
+                    Some(timestamp) => month_file(path, timestamp(record)),
+                    None => path.clone(),
+                };
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent).await?;
+                }
+                let mut file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&path)
+                    .await?;
+                file.write_all(format!("{}\n", line).as_bytes()).await?;
+            }
+            None => memory.push(line),
+        }
+        Ok(())
+    }
+
+    /// Read all records. Lines that cannot be parsed are skipped with a warning.
+    pub async fn read_all(&self) -> Result<Vec<T>, Box<dyn std::error::Error + Send + Sync>> {
+        let memory = self.memory.lock().await;
+        let content = match &self.path {
+            Some(path) => {
+                let mut content = read_file(path).await?;
+                if self.partition.is_some() {
+                    for (_, file) in month_files(path).await? {
+                        content.push('\n');
+                        content.push_str(&read_file(&file).await?);
+                    }
+                }
+                content
+            }
+            None => memory.join("\n"),
+        };
+        Ok(parse_lines(&content))
+    }
+}
+
+impl<T: Serialize + DeserializeOwned + Timestamped> JsonLines<T> {
+    /// Open a store that writes each record to a file per month, e.g. `history-2025-01.jsonl`
+    /// for `history.jsonl`, so that [`JsonLines::read_range`] only reads the months it covers.
+    /// Records in `path` itself, written before the store was partitioned, are still read.
+    pub fn open_monthly(path: impl Into<PathBuf>) -> Self {
+        JsonLines {
+            partition: Some(T::timestamp),
+            ..Self::open(path)
+        }
+    }
+
+    /// Read the records with `from <= timestamp < to`, in the order they were written
+    pub async fn read_range(
+        &self,
+        from: DateTime<Local>,
+        to: DateTime<Local>,
+    ) -> Result<Vec<T>, Box<dyn std::error::Error + Send + Sync>> {
+        let records = match (&self.path, self.partition) {
+            (Some(path), Some(_)) => {
+                let _memory = self.memory.lock().await;
+                // `to` is exclusive, so a range ending at midnight on the 1st skips that month
+                let last = to
+                    .checked_sub_signed(TimeDelta::nanoseconds(1))
+                    .unwrap_or(to);
+                let months = (from.year(), from.month())..=(last.year(), last.month());
+                let mut content = read_file(path).await?;
+                for (month, file) in month_files(path).await? {
+                    if months.contains(&(month.year(), month.month())) {
+                        content.push('\n');
+                        content.push_str(&read_file(&file).await?);
+                    }
+                }
+                parse_lines(&content)
+            }
+            _ => self.read_all().await?,
+        };
+        Ok(records
+            .into_iter()
+            .filter(|r| r.timestamp() >= from && r.timestamp() < to)
+            .collect())
+    }
+}
+
+/// The file of `path` for the month of `timestamp`: `dir/name.ext` becomes `dir/name-YYYY-MM.ext`
+fn month_file(path: &Path, timestamp: DateTime<Local>) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let mut file = path.with_file_name(format!("{}-{}", stem, timestamp.format("%Y-%m")));
+    if let Some(extension) = path.extension() {
+        file.set_extension(extension);
+    }
+    file
+}
+
+/// The month files of `path` that exist, with the first day of their month, sorted by month
+async fn month_files(
+    path: &Path,
+) -> Result<Vec<(NaiveDate, PathBuf)>, Box<dyn std::error::Error + Send + Sync>> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let suffix = path
+        .extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+    let mut entries = match fs::read_dir(dir).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(Box::new(e)),
+    };
+    let mut files = vec![];
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let month = name
+            .strip_prefix(&format!("{}-", stem))
+            .and_then(|rest| rest.strip_suffix(&suffix))
+            .and_then(|month| NaiveDate::parse_from_str(&format!("{}-01", month), "%Y-%m-%d").ok());
+        if let Some(month) = month {
+            files.push((month, entry.path()));
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+async fn read_file(path: &Path) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    match fs::read_to_string(path).await {
+        Ok(content) => Ok(content),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(String::new()),
+        Err(e) => Err(Box::new(e)),
+    }
+}
+
+/// Parse one record per line. Lines that cannot be parsed are skipped with a warning.
+fn parse_lines<T: DeserializeOwned>(content: &str) -> Vec<T> {
+    content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| match serde_json::from_str(line) {
+            Ok(record) => Some(record),
+            Err(e) => {
+                warn!("Skipping malformed record: {:?}", e);
+                None
+            }
+        })
+        .collect()
+}
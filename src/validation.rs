@@ -0,0 +1,559 @@
+use crate::config::{AppConfig, HysteresisConfig};
+use crate::controller::ControllerSettings;
+use crate::ecos::data_models::{ChargeModeSettingsRequest, ChargeSchedule, MAX_WINDOW_MINUTES};
+use crate::state::ChargeMode;
+use chrono::{DateTime, Local};
+use rocket::http::Status;
+use rocket::response::status::Custom;
+use rocket::serde::json::Json;
+use rocket::serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// Longest duration of a timed mode, in minutes
+pub const MAX_DURATION: u64 = 24 * 60;
+/// Largest side load of the active mode, in watts
+pub const MAX_SIDE_LOAD: u32 = 10_000;
+/// Largest fixed charge or discharge power, in watts
+pub const MAX_POWER: u32 = 5000;
+/// Largest gain of the power controller
+pub const MAX_GAIN: f32 = 10.0;
+/// Shortest check interval of the periodic modes, in seconds. The charging schedule is set in whole minutes.
+pub const MIN_CHECK_INTERVAL: u64 = 60;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+#[serde(crate = "rocket::serde")]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+/// The body of a `422 Unprocessable Entity` response
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+#[serde(crate = "rocket::serde")]
+pub struct ValidationError {
+    pub message: String,
+    pub errors: Vec<FieldError>,
+}
+
+impl ValidationError {
+    pub fn new(errors: Vec<FieldError>) -> Self {
+        ValidationError {
+            message: "Invalid charge mode".to_string(),
+            errors,
+        }
+    }
+
+    /// Invalid charge mode settings of the device
+    pub fn settings(errors: Vec<FieldError>) -> Self {
+        ValidationError {
+            message: "Invalid charge mode settings".to_string(),
+            errors,
+        }
+    }
+
+    /// An invalid request to save a settings snapshot
+    pub fn snapshot(errors: Vec<FieldError>) -> Self {
+        ValidationError {
+            message: "Invalid snapshot".to_string(),
+            errors,
+        }
+    }
+
+    /// An invalid config file
+    pub fn config(errors: Vec<FieldError>) -> Self {
+        ValidationError {
+            message: "Invalid config".to_string(),
+            errors,
+        }
+    }
+
+    /// A request body that is not valid JSON or does not match any mode
+    pub fn malformed(error: &rocket::serde::json::Error<'_>) -> Self {
+        let message = match error {
+            rocket::serde::json::Error::Io(e) => e.to_string(),
+            rocket::serde::json::Error::Parse(_, e) => e.to_string(),
+        };
+        ValidationError::new(vec![FieldError {
+            field: "body".to_string(),
+            message,
+        }])
+    }
+
+    pub fn into_response(self) -> Custom<Json<ValidationError>> {
+        Custom(Status::UnprocessableEntity, Json(self))
+    }
+}
+
+fn check(errors: &mut Vec<FieldError>, valid: bool, field: &str, message: String) {
+    if !valid {
+        errors.push(FieldError {
+            field: field.to_string(),
+            message,
+        });
+    }
+}
+
+fn check_battery_level(
+    errors: &mut Vec<FieldError>,
+    field: &str,
+    battery_level: u8,
+    app_config: &AppConfig,
+) {
+    check(
+        errors,
+        battery_level <= 100,
+        field,
+        format!("must be between 0 and 100, got {}", battery_level),
+    );
+    check(
+        errors,
+        battery_level as i32 >= app_config.epsBatteryMin,
+        field,
+        format!(
+            "must be at least epsBatteryMin ({}), got {}",
+            app_config.epsBatteryMin, battery_level
+        ),
+    );
+}
+
+fn check_duration(errors: &mut Vec<FieldError>, duration: u64) {
+    check(
+        errors,
+        (1..=MAX_DURATION).contains(&duration),
+        "duration",
+        format!(
+            "must be between 1 and {} minutes, got {}",
+            MAX_DURATION, duration
+        ),
+    );
+}
+
+fn check_power(errors: &mut Vec<FieldError>, power: u32) {
+    check(
+        errors,
+        (1..=MAX_POWER).contains(&power),
+        "power",
+        format!("must be between 1 and {} W, got {}", MAX_POWER, power),
+    );
+}
+
+/// The longest check interval of the periodic modes, in seconds: their windows last until the next check
+/// and for the hold time on top, and a window cannot span a whole day
+fn max_check_interval(hysteresis: &HysteresisConfig) -> u64 {
+    (MAX_WINDOW_MINUTES as u64 * 60).saturating_sub(hysteresis.window_hold())
+}
+
+fn check_interval_length(
+    errors: &mut Vec<FieldError>,
+    field: &str,
+    check_interval: u64,
+    hysteresis: &HysteresisConfig,
+) {
+    let max = max_check_interval(hysteresis);
+    check(
+        errors,
+        (MIN_CHECK_INTERVAL..=max).contains(&check_interval),
+        field,
+        format!(
+            "must be between {} and {} seconds, got {}",
+            MIN_CHECK_INTERVAL, max, check_interval
+        ),
+    );
+}
+
+fn check_controller(errors: &mut Vec<FieldError>, controller: &ControllerSettings) {
+    for (field, gain) in [
+        ("controller.kp", controller.kp),
+        ("controller.ki", controller.ki),
+    ] {
+        check(
+            errors,
+            (0.0..=MAX_GAIN).contains(&gain),
+            field,
+            format!("must be between 0 and {}, got {}", MAX_GAIN, gain),
+        );
+    }
+    check(
+        errors,
+        controller.smoothing > 0.0 && controller.smoothing <= 1.0,
+        "controller.smoothing",
+        format!(
+            "must be above 0 and at most 1, got {}",
+            controller.smoothing
+        ),
+    );
+    if let Some(max_step) = controller.max_step {
+        check(
+            errors,
+            max_step >= 1,
+            "controller.max_step",
+            format!("must be at least 1 W, got {}", max_step),
+        );
+    }
+}
+
+/// `until` replaces `duration` when it is set
+fn check_end(errors: &mut Vec<FieldError>, duration: u64, until: Option<DateTime<Local>>) {
+    let Some(until) = until else {
+        check_duration(errors, duration);
+        return;
+    };
+    let now = Local::now();
+    check(
+        errors,
+        until > now,
+        "until",
+        format!("must be in the future, got {}", until.to_rfc3339()),
+    );
+    check(
+        errors,
+        until <= now + chrono::Duration::minutes(MAX_DURATION as i64),
+        "until",
+        format!(
+            "must be within {} minutes, got {}",
+            MAX_DURATION,
+            until.to_rfc3339()
+        ),
+    );
+}
+
+/// Check a requested mode against the limits of the device, listing every invalid field
+pub fn validate_mode(
+    charge_mode: &ChargeMode,
+    app_config: &AppConfig,
+) -> Result<(), ValidationError> {
+    let mut errors = vec![];
+    match *charge_mode {
+        ChargeMode::Conservative {
+            battery_level,
+            duration,
+            until,
+        } => {
+            check_battery_level(&mut errors, "battery_level", battery_level, app_config);
+            check_end(&mut errors, duration, until);
+        }
+        ChargeMode::Active {
+            side_load,
+            duration,
+            until,
+            check_interval,
+            ..
+        } => {
+            check(
+                &mut errors,
+                side_load <= MAX_SIDE_LOAD,
+                "side_load",
+                format!("must be at most {} W, got {}", MAX_SIDE_LOAD, side_load),
+            );
+            check_end(&mut errors, duration, until);
+            if let Some(check_interval) = check_interval {
+                check_interval_length(
+                    &mut errors,
+                    "check_interval",
+                    check_interval,
+                    &app_config.hysteresis,
+                );
+            }
+        }
+        ChargeMode::SelfSufficient { battery_level } => {
+            check_battery_level(&mut errors, "battery_level", battery_level, app_config);
+        }
+        ChargeMode::Backup { reserve_soc } => {
+            check_battery_level(&mut errors, "reserve_soc", reserve_soc, app_config);
+        }
+        ChargeMode::ForceCharge {
+            target_soc,
+            power,
+            duration,
+            until,
+        } => {
+            check(
+                &mut errors,
+                (1..=100).contains(&target_soc),
+                "target_soc",
+                format!("must be between 1 and 100, got {}", target_soc),
+            );
+            check_power(&mut errors, power);
+            check_end(&mut errors, duration, until);
+        }
+        ChargeMode::ForceExport {
+            power,
+            floor_soc,
+            duration,
+            until,
+        } => {
+            check_power(&mut errors, power);
+            check_battery_level(&mut errors, "floor_soc", floor_soc, app_config);
+            check_end(&mut errors, duration, until);
+        }
+        ChargeMode::ExportLimit {
+            duration,
+            until,
+            check_interval,
+            ..
+        } => {
+            check_end(&mut errors, duration, until);
+            if let Some(check_interval) = check_interval {
+                check_interval_length(
+                    &mut errors,
+                    "check_interval",
+                    check_interval,
+                    &app_config.hysteresis,
+                );
+            }
+        }
+        ChargeMode::PeakShave {
+            reserve_soc,
+            duration,
+            until,
+            check_interval,
+            ..
+        } => {
+            check_battery_level(&mut errors, "reserve_soc", reserve_soc, app_config);
+            check_end(&mut errors, duration, until);
+            if let Some(check_interval) = check_interval {
+                check_interval_length(
+                    &mut errors,
+                    "check_interval",
+                    check_interval,
+                    &app_config.hysteresis,
+                );
+            }
+        }
+    }
+    if let Some(controller) = charge_mode.controller() {
+        check_controller(&mut errors, controller);
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(ValidationError::new(errors))
+    }
+}
+
+fn check_percent(errors: &mut Vec<FieldError>, field: &str, value: i32) {
+    check(
+        errors,
+        (0..=100).contains(&value),
+        field,
+        format!("must be between 0 and 100, got {}", value),
+    );
+}
+
+fn check_flag(errors: &mut Vec<FieldError>, field: &str, value: i32) {
+    check(
+        errors,
+        value == 0 || value == 1,
+        field,
+        format!("must be 0 or 1, got {}", value),
+    );
+}
+
+fn check_schedules(errors: &mut Vec<FieldError>, list: &str, schedules: &[ChargeSchedule]) {
+    for (i, schedule) in schedules.iter().enumerate() {
+        for (name, value, max) in [
+            ("startHour", schedule.startHour, 23),
+            ("startMinute", schedule.startMinute, 59),
+            ("endHour", schedule.endHour, 23),
+            ("endMinute", schedule.endMinute, 59),
+            ("power", schedule.power, MAX_POWER as i32),
+        ] {
+            check(
+                errors,
+                (0..=max).contains(&value),
+                &format!("{}[{}].{}", list, i, name),
+                format!("must be between 0 and {}, got {}", max, value),
+            );
+        }
+        check_flag(
+            errors,
+            &format!("{}[{}].abandonPv", list, i),
+            schedule.abandonPv,
+        );
+    }
+}
+
+/// Check charge mode settings before they are written to the device, listing every invalid field
+pub fn validate_settings(request: &ChargeModeSettingsRequest) -> Result<(), ValidationError> {
+    let mut errors = vec![];
+    check(
+        &mut errors,
+        (0..=2).contains(&request.chargeUseMode),
+        "chargeUseMode",
+        format!(
+            "must be 0 (self-sufficient), 1 (time of use) or 2 (backup), got {}",
+            request.chargeUseMode
+        ),
+    );
+    check_percent(&mut errors, "minCapacity", request.minCapacity);
+    check_percent(&mut errors, "epsBatteryMin", request.epsBatteryMin);
+    check(
+        &mut errors,
+        request.minCapacity >= request.epsBatteryMin,
+        "minCapacity",
+        format!(
+            "must be at least epsBatteryMin ({}), got {}",
+            request.epsBatteryMin, request.minCapacity
+        ),
+    );
+    check_percent(&mut errors, "maxFeedIn", request.maxFeedIn);
+    check_flag(
+        &mut errors,
+        "dischargeToGridFlag",
+        request.dischargeToGridFlag,
+    );
+    check_schedules(&mut errors, "chargingList", &request.chargingList);
+    check_schedules(&mut errors, "dischargingList", &request.dischargingList);
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(ValidationError::settings(errors))
+    }
+}
+
+/// Check the `[app]` table of the config before it replaces the running one
+pub fn validate_config(app_config: &AppConfig) -> Result<(), ValidationError> {
+    let mut errors = vec![];
+    check(
+        &mut errors,
+        !app_config.deviceId.is_empty(),
+        "deviceId",
+        "must not be empty".to_string(),
+    );
+    let max_hold = MAX_WINDOW_MINUTES as u64 * 60 - MIN_CHECK_INTERVAL;
+    check(
+        &mut errors,
+        app_config.hysteresis.hold <= max_hold,
+        "hysteresis.hold",
+        format!(
+            "must be at most {} seconds, got {}",
+            max_hold, app_config.hysteresis.hold
+        ),
+    );
+    check_interval_length(
+        &mut errors,
+        "checkInterval",
+        app_config.checkInterval,
+        &app_config.hysteresis,
+    );
+    if let Err(e) = validate_settings(&app_config.settings_request()) {
+        errors.extend(e.errors);
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(ValidationError::config(errors))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_mode() {
+        let app_config = AppConfig::new();
+        assert!(validate_mode(
+            &ChargeMode::Active {
+                side_load: 1000,
+                duration: 60,
+                until: None,
+                check_interval: Some(300),
+                controller: None,
+            },
+            &app_config
+        )
+        .is_ok());
+
+        let error = validate_mode(
+            &ChargeMode::Active {
+                side_load: 4_000_000_000,
+                duration: 0,
+                until: None,
+                check_interval: Some(10),
+                controller: Some(ControllerSettings {
+                    kp: -1.0,
+                    smoothing: 0.0,
+                    ..ControllerSettings::default()
+                }),
+            },
+            &app_config,
+        )
+        .unwrap_err();
+        let fields: Vec<&str> = error.errors.iter().map(|e| e.field.as_str()).collect();
+        assert_eq!(
+            fields,
+            [
+                "side_load",
+                "duration",
+                "check_interval",
+                "controller.kp",
+                "controller.smoothing"
+            ]
+        );
+
+        let error = validate_mode(
+            &ChargeMode::SelfSufficient { battery_level: 5 },
+            &app_config,
+        )
+        .unwrap_err();
+        assert_eq!(error.errors[0].field, "battery_level");
+    }
+
+    #[test]
+    fn test_check_interval_fits_a_window() {
+        let mut app_config = AppConfig::new();
+        app_config.hysteresis = HysteresisConfig {
+            power: 200,
+            hold: 600,
+        };
+        let active = |check_interval| ChargeMode::Active {
+            side_load: 1000,
+            duration: 60,
+            until: None,
+            check_interval: Some(check_interval),
+            controller: None,
+        };
+        // 23:59 of window, less the hold time
+        assert!(validate_mode(&active(86_340 - 600), &app_config).is_ok());
+        let error = validate_mode(&active(86_340 - 599), &app_config).unwrap_err();
+        assert_eq!(error.errors[0].field, "check_interval");
+
+        app_config.checkInterval = 86_400;
+        app_config.hysteresis.hold = 86_400;
+        let error = validate_config(&app_config).unwrap_err();
+        let fields: Vec<&str> = error.errors.iter().map(|e| e.field.as_str()).collect();
+        assert_eq!(fields, ["hysteresis.hold", "checkInterval"]);
+    }
+
+    #[test]
+    fn test_validate_settings() {
+        let mut request = ChargeModeSettingsRequest {
+            _t: 0,
+            clientType: "BROWSER".to_string(),
+            clientVersion: "1.0".to_string(),
+            deviceId: "123456".to_string(),
+            chargeUseMode: 1,
+            minCapacity: 20,
+            maxFeedIn: 50,
+            dischargeToGridFlag: 1,
+            chargingList: vec![ChargeSchedule::from_now(60, 3000)],
+            dischargingList: vec![],
+            epsBatteryMin: 10,
+        };
+        assert!(validate_settings(&request).is_ok());
+
+        request.minCapacity = 5;
+        request.maxFeedIn = 120;
+        request.dischargingList = vec![ChargeSchedule {
+            startHour: 24,
+            ..ChargeSchedule::from_now(60, 3000)
+        }];
+        let error = validate_settings(&request).unwrap_err();
+        let fields: Vec<&str> = error.errors.iter().map(|e| e.field.as_str()).collect();
+        assert_eq!(
+            fields,
+            ["minCapacity", "maxFeedIn", "dischargingList[0].startHour"]
+        );
+    }
+}
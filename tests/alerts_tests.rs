@@ -0,0 +1,98 @@
+mod common;
+
+use common::ecos::MockEcos;
+use ecactus_controller::alerts::{self, Alert};
+use ecactus_controller::audit::Actor;
+use ecactus_controller::config::{AlertsConfig, AppConfig};
+use ecactus_controller::ecos::client::EcosClient;
+use ecactus_controller::state::{AppState, ChargeMode};
+use std::sync::Arc;
+
+fn alerts_config() -> AlertsConfig {
+    AlertsConfig {
+        url: "http://localhost/alerts".to_string(),
+        regions: vec!["Greater Sydney".to_string()],
+        events: vec![],
+        interval: 300,
+        reserve_soc: 90,
+    }
+}
+
+fn storm() -> Alert {
+    Alert {
+        title: "Severe Thunderstorm Warning".to_string(),
+        event: "Severe Thunderstorm Warning".to_string(),
+        area: "Greater Sydney".to_string(),
+        expires: None,
+    }
+}
+
+#[rocket::async_test]
+async fn test_alert_sets_and_ends_backup_mode() {
+    let ecos = MockEcos::start().await;
+    let state = Arc::new(AppState::new(
+        AppConfig::new(),
+        Arc::new(EcosClient::new(
+            "user".to_string(),
+            "password".to_string(),
+            ecos.base_url.clone(),
+        )),
+    ));
+    let config = alerts_config();
+
+    let active = alerts::check(&state, &config, &[storm()], false).await;
+    assert!(active);
+    assert!(matches!(
+        *state.current_mode.lock().await,
+        ChargeMode::Backup { reserve_soc: 90 }
+    ));
+    let posted = ecos.wait_for_posts(1).await;
+    assert_eq!(posted[0]["chargeUseMode"], 2);
+    assert_eq!(posted[0]["minCapacity"], 90);
+    assert_eq!(posted[0]["epsBatteryMin"], 90);
+    let settings = ecos.settings().await;
+    assert_eq!(settings["minCapacity"], 90);
+
+    // the alert is still active: nothing changes
+    assert!(alerts::check(&state, &config, &[storm()], active).await);
+    assert_eq!(state.current_mode.lock().await.name(), "backup");
+
+    let active = alerts::check(&state, &config, &[], active).await;
+    assert!(!active);
+    assert_eq!(state.current_mode.lock().await.name(), "self-sufficient");
+    let posted = ecos.wait_for_posts(2).await;
+    assert_eq!(posted[1]["chargeUseMode"], 0);
+}
+
+#[rocket::async_test]
+async fn test_alert_leaves_other_modes_alone_once_set() {
+    let state = Arc::new(AppState::new(
+        AppConfig::new(),
+        Arc::new(EcosClient::new(
+            "user".to_string(),
+            "password".to_string(),
+            "http://127.0.0.1:9".to_string(),
+        )),
+    ));
+    let config = alerts_config();
+
+    // another mode set during the alert stays until the alert ends
+    let active = alerts::check(&state, &config, &[storm()], false).await;
+    state
+        .update_mode(
+            ChargeMode::SelfSufficient { battery_level: 20 },
+            Actor::controller("test"),
+        )
+        .await;
+    assert!(alerts::check(&state, &config, &[storm()], active).await);
+    assert_eq!(state.current_mode.lock().await.name(), "self-sufficient");
+    assert!(!alerts::check(&state, &config, &[], active).await);
+
+    // alerts for other regions are ignored
+    let elsewhere = Alert {
+        area: "Hunter".to_string(),
+        ..storm()
+    };
+    assert!(!alerts::check(&state, &config, &[elsewhere], false).await);
+    assert_eq!(state.current_mode.lock().await.name(), "self-sufficient");
+}
@@ -0,0 +1,89 @@
+use ecactus_controller::audit::{Actor, AuditEntry, AuditEvent};
+use ecactus_controller::config::AppConfig;
+use ecactus_controller::ecos::client::EcosClient;
+use ecactus_controller::routes;
+use ecactus_controller::state::{AppState, ChargeMode};
+use rocket::http::{ContentType, Status};
+use rocket::local::asynchronous::Client;
+use rocket::serde::json::{json, serde_json};
+use std::sync::Arc;
+
+async fn create_client() -> Client {
+    let app_state = Arc::new(AppState::new(
+        AppConfig::new(),
+        Arc::new(EcosClient::new(
+            "user".to_string(),
+            "password".to_string(),
+            "http://localhost".to_string(),
+        )),
+    ));
+    let rocket = rocket::build()
+        .manage(app_state)
+        .mount("/", routes::charge_mode::routes())
+        .mount("/", routes::audit::routes());
+
+    Client::tracked(rocket)
+        .await
+        .expect("valid rocket instance")
+}
+
+async fn get_audit(client: &Client, query: &str) -> Vec<AuditEntry> {
+    let response = client.get(format!("/audit{}", query)).dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    let body = response.into_string().await.expect("response into string");
+    serde_json::from_str(&body).expect("parse audit entries")
+}
+
+#[rocket::async_test]
+async fn test_audit_records_mode_change() {
+    let client = create_client().await;
+
+    let payload = json!({
+        "mode": "conservative",
+        "battery_level": 80,
+        "duration": 60
+    });
+    let response = client
+        .post("/charge-mode")
+        .header(ContentType::JSON)
+        .body(payload.to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+
+    let entries = get_audit(&client, "?kind=mode-change").await;
+    assert_eq!(entries.len(), 1);
+    assert!(matches!(entries[0].actor, Actor::Api { .. }));
+    match &entries[0].event {
+        AuditEvent::ModeChange {
+            mode: ChargeMode::Conservative { battery_level, .. },
+        } => assert_eq!(*battery_level, 80),
+        other => panic!("Expected a conservative mode change, got {:?}", other),
+    }
+}
+
+#[rocket::async_test]
+async fn test_audit_records_failed_settings_write() {
+    let client = create_client().await;
+
+    let response = client.put("/charge-mode/reset").dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+
+    let entries = get_audit(&client, "?kind=settings-write&limit=1").await;
+    assert_eq!(entries.len(), 1);
+    assert!(matches!(entries[0].actor, Actor::Controller { .. }));
+    match &entries[0].event {
+        AuditEvent::SettingsWrite {
+            request,
+            response,
+            error,
+            ..
+        } => {
+            assert_eq!(request.minCapacity, 10);
+            assert!(response.is_none());
+            // the ECOS server is not reachable in the tests
+            assert!(error.is_some());
+        }
+        other => panic!("Expected a settings write, got {:?}", other),
+    }
+}
@@ -0,0 +1,148 @@
+mod common;
+
+use common::ecos::MockEcos;
+use ecactus_controller::audit::{Actor, AuditEntry};
+use ecactus_controller::config::{ApiKey, AppConfig, AuthConfig, Scope};
+use ecactus_controller::ecos::client::EcosClient;
+use ecactus_controller::routes;
+use ecactus_controller::state::AppState;
+use rocket::http::{ContentType, Header, Status};
+use rocket::local::asynchronous::Client;
+use rocket::serde::json::{json, serde_json};
+use std::sync::Arc;
+
+async fn create_client(ecos: &MockEcos) -> Client {
+    let auth = AuthConfig {
+        keys: vec![
+            ApiKey {
+                name: "dashboard".to_string(),
+                key: "read-key".to_string(),
+                scope: Scope::Read,
+            },
+            ApiKey {
+                name: "automation".to_string(),
+                key: "control-key".to_string(),
+                scope: Scope::Control,
+            },
+        ],
+        open: false,
+    };
+    create_client_with(ecos, auth).await
+}
+
+async fn create_client_with(ecos: &MockEcos, auth: AuthConfig) -> Client {
+    let app_state = Arc::new(AppState {
+        auth,
+        ..AppState::new(
+            AppConfig::new(),
+            Arc::new(EcosClient::new(
+                "user".to_string(),
+                "password".to_string(),
+                ecos.base_url.clone(),
+            )),
+        )
+    });
+    let rocket = rocket::build()
+        .manage(app_state)
+        .mount("/", routes::charge_mode::routes())
+        .mount("/", routes::audit::routes())
+        .mount("/ecos", routes::ecos::routes());
+
+    Client::tracked(rocket)
+        .await
+        .expect("valid rocket instance")
+}
+
+fn bearer(key: &str) -> Header<'static> {
+    Header::new("Authorization", format!("Bearer {}", key))
+}
+
+#[rocket::async_test]
+async fn test_requests_without_a_valid_key_are_rejected() {
+    let ecos = MockEcos::start().await;
+    let client = create_client(&ecos).await;
+
+    let response = client.get("/charge-mode").dispatch().await;
+    assert_eq!(response.status(), Status::Unauthorized);
+
+    let response = client
+        .get("/ecos/run-data")
+        .header(bearer("wrong-key"))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Unauthorized);
+
+    let response = client.put("/charge-mode/reset").dispatch().await;
+    assert_eq!(response.status(), Status::Unauthorized);
+    assert_eq!(ecos.posted().await.len(), 0);
+}
+
+#[rocket::async_test]
+async fn test_without_keys_the_api_needs_open() {
+    let ecos = MockEcos::start().await;
+    let client = create_client_with(&ecos, AuthConfig::default()).await;
+    let response = client.get("/charge-mode").dispatch().await;
+    assert_eq!(response.status(), Status::Unauthorized);
+
+    let client = create_client_with(&ecos, AuthConfig::open()).await;
+    let response = client.get("/charge-mode").dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+}
+
+#[rocket::async_test]
+async fn test_read_scope_cannot_control() {
+    let ecos = MockEcos::start().await;
+    let client = create_client(&ecos).await;
+
+    let response = client
+        .get("/ecos/run-data")
+        .header(bearer("read-key"))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+
+    let response = client
+        .get("/charge-mode")
+        .header(Header::new("X-API-Key", "read-key"))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+
+    let response = client
+        .post("/charge-mode")
+        .header(ContentType::JSON)
+        .header(bearer("read-key"))
+        .body(json!({ "mode": "self-sufficient", "battery_level": 20 }).to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Forbidden);
+}
+
+#[rocket::async_test]
+async fn test_control_scope_is_audited_with_the_key_name() {
+    let ecos = MockEcos::start().await;
+    let client = create_client(&ecos).await;
+
+    let response = client
+        .post("/charge-mode")
+        .header(ContentType::JSON)
+        .header(bearer("control-key"))
+        .body(json!({ "mode": "self-sufficient", "battery_level": 20 }).to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+
+    let response = client
+        .get("/audit?kind=mode-change")
+        .header(bearer("control-key"))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    let body = response.into_string().await.expect("response into string");
+    let entries: Vec<AuditEntry> = serde_json::from_str(&body).expect("parse audit entries");
+    assert_eq!(entries.len(), 1);
+    match &entries[0].actor {
+        Actor::Api { key, .. } => assert_eq!(key.as_deref(), Some("automation")),
+        other => panic!("Expected an API caller, got {:?}", other),
+    }
+}
@@ -1,29 +1,30 @@
-use ecactus_controller::config::AppConfig;
+mod common;
+
+use chrono::{Local, Timelike};
+use common::ecos::MockEcos;
+use ecactus_controller::audit::Actor;
+use ecactus_controller::config::{AppConfig, CacheConfig, HysteresisConfig};
 use ecactus_controller::ecos::client::EcosClient;
 use ecactus_controller::routes;
 use ecactus_controller::state::{AppState, ChargeMode};
+use ecactus_controller::validation::ValidationError;
 use rocket::http::{ContentType, Status};
 use rocket::local::asynchronous::Client;
 use rocket::serde::json::{json, serde_json};
 use rocket::{routes, tokio};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+fn get_ecos_client() -> Arc<EcosClient> {
+    Arc::new(EcosClient::new(
+        "user".to_string(),
+        "password".to_string(),
+        "http://localhost".to_string(),
+    ))
+}
 
 fn get_self_sufficient_app_state() -> Arc<AppState> {
-    let config = AppConfig::new();
-    let app_state = Arc::new(AppState {
-        current_mode: tokio::sync::Mutex::new(ChargeMode::SelfSufficient {
-            battery_level: config.minCapacity as u8,
-        }),
-        expiration: tokio::sync::Mutex::new(None),
-        background_task: tokio::sync::Mutex::new(None),
-        app_config: config,
-        ecos_client: Arc::new(EcosClient::new(
-            "user".to_string(),
-            "password".to_string(),
-            "http://localhost".to_string(),
-        )),
-    });
-    app_state
+    Arc::new(AppState::new(AppConfig::new(), get_ecos_client()))
 }
 async fn create_client(app_state: Arc<AppState>, routes: Vec<rocket::Route>) -> Client {
     let rocket = rocket::build().manage(app_state).mount("/", routes);
@@ -95,15 +96,10 @@ async fn test_put_charge_mode_reset() {
         current_mode: tokio::sync::Mutex::new(ChargeMode::Conservative {
             battery_level: 80,
             duration: 60,
+            until: None,
         }),
         expiration: tokio::sync::Mutex::new(Some(std::time::Instant::now())),
-        background_task: tokio::sync::Mutex::new(None),
-        app_config: AppConfig::new(),
-        ecos_client: Arc::new(EcosClient::new(
-            "user".to_string(),
-            "password".to_string(),
-            "http://localhost".to_string(),
-        )),
+        ..AppState::new(AppConfig::new(), get_ecos_client())
     });
 
     let client = create_client(app_state, routes![routes::charge_mode::reset_mode]).await;
@@ -121,3 +117,921 @@ async fn test_put_charge_mode_reset() {
         panic!("Expected SelfSufficient mode");
     }
 }
+
+async fn post_invalid(body: &str) -> ValidationError {
+    let app_state = get_self_sufficient_app_state();
+    let client = create_client(app_state, routes![routes::charge_mode::set_mode]).await;
+
+    let response = client
+        .post("/charge-mode")
+        .header(ContentType::JSON)
+        .body(body)
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::UnprocessableEntity);
+
+    let state = client.rocket().state::<Arc<AppState>>().unwrap();
+    assert!(matches!(
+        *state.current_mode.lock().await,
+        ChargeMode::SelfSufficient { .. }
+    ));
+
+    let body = response.into_string().await.expect("response into string");
+    serde_json::from_str(&body).expect("parse validation error")
+}
+
+#[rocket::async_test]
+async fn test_post_charge_mode_invalid_fields() {
+    let error = post_invalid(
+        &json!({
+            "mode": "conservative",
+            "battery_level": 250,
+            "duration": 0
+        })
+        .to_string(),
+    )
+    .await;
+    let fields: Vec<&str> = error.errors.iter().map(|e| e.field.as_str()).collect();
+    assert_eq!(fields, ["battery_level", "duration"]);
+
+    let error = post_invalid(
+        &json!({
+            "mode": "active",
+            "side_load": 4_000_000_000u32,
+            "duration": 60,
+            "check_interval": 5
+        })
+        .to_string(),
+    )
+    .await;
+    let fields: Vec<&str> = error.errors.iter().map(|e| e.field.as_str()).collect();
+    assert_eq!(fields, ["side_load", "check_interval"]);
+}
+
+#[rocket::async_test]
+async fn test_post_charge_mode_below_eps_battery_min() {
+    let error = post_invalid(
+        &json!({
+            "mode": "self-sufficient",
+            "battery_level": 5
+        })
+        .to_string(),
+    )
+    .await;
+    assert_eq!(error.errors.len(), 1);
+    assert_eq!(error.errors[0].field, "battery_level");
+    assert!(error.errors[0].message.contains("epsBatteryMin"));
+}
+
+#[rocket::async_test]
+async fn test_post_charge_mode_malformed() {
+    let error = post_invalid("{\"mode\": \"conservative\", \"battery_level\": ").await;
+    assert_eq!(error.errors[0].field, "body");
+
+    let error = post_invalid(&json!({ "mode": "turbo" }).to_string()).await;
+    assert_eq!(error.errors[0].field, "body");
+    assert!(error.errors[0].message.contains("turbo"));
+}
+
+async fn create_mock_client(ecos: &MockEcos) -> Client {
+    create_mock_client_with(ecos, AppConfig::new()).await
+}
+
+async fn create_mock_client_with(ecos: &MockEcos, app_config: AppConfig) -> Client {
+    // modes that watch the battery SoC must see every change of the mock run data
+    let cache = CacheConfig {
+        run_data: 0,
+        ..CacheConfig::default()
+    };
+    let app_state = Arc::new(AppState::new(
+        app_config,
+        Arc::new(
+            EcosClient::new(
+                "user".to_string(),
+                "password".to_string(),
+                ecos.base_url.clone(),
+            )
+            .with_cache(&cache),
+        ),
+    ));
+    create_client(
+        app_state,
+        routes![
+            routes::charge_mode::set_mode,
+            routes::charge_mode::adjust_mode
+        ],
+    )
+    .await
+}
+
+#[rocket::async_test]
+async fn test_patch_charge_mode_extends_conservative() {
+    let ecos = MockEcos::start().await;
+    let client = create_mock_client(&ecos).await;
+    let payload = json!({ "mode": "conservative", "battery_level": 80, "duration": 60 });
+    let response = client
+        .post("/charge-mode")
+        .header(ContentType::JSON)
+        .body(payload.to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    ecos.wait_for_posts(1).await;
+
+    let state = client.rocket().state::<Arc<AppState>>().unwrap();
+    let expiration = state.expiration.lock().await.unwrap();
+
+    let response = client
+        .patch("/charge-mode")
+        .header(ContentType::JSON)
+        .body(json!({ "duration": 90, "battery_level": 70 }).to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    let charge_mode: ChargeMode = response.into_json().await.expect("charge mode");
+    assert!(matches!(
+        charge_mode,
+        ChargeMode::Conservative {
+            battery_level: 70,
+            duration: 90,
+            ..
+        }
+    ));
+
+    // the expiration moves by the extra 30 minutes and the running task applies the new level
+    let new_expiration = state.expiration.lock().await.unwrap();
+    let extension = new_expiration - expiration;
+    assert!(extension.abs_diff(Duration::from_secs(30 * 60)) < Duration::from_secs(1));
+    let posted = ecos.wait_for_posts(2).await;
+    assert_eq!(posted[1]["minCapacity"], 70);
+    assert!(state
+        .background_task
+        .lock()
+        .await
+        .as_ref()
+        .is_some_and(|task| !task.is_finished()));
+}
+
+#[rocket::async_test]
+async fn test_patch_charge_mode_moves_until() {
+    let ecos = MockEcos::start().await;
+    let client = create_mock_client(&ecos).await;
+    let payload = json!({ "mode": "conservative", "battery_level": 80, "duration": 60 });
+    let response = client
+        .post("/charge-mode")
+        .header(ContentType::JSON)
+        .body(payload.to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    ecos.wait_for_posts(1).await;
+    let state = client.rocket().state::<Arc<AppState>>().unwrap();
+    let expiration = state.expiration.lock().await.unwrap();
+
+    let until = (Local::now() + chrono::Duration::hours(3))
+        .with_nanosecond(0)
+        .unwrap();
+    let response = client
+        .patch("/charge-mode")
+        .header(ContentType::JSON)
+        .body(json!({ "until": until.to_rfc3339() }).to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    let charge_mode: ChargeMode = response.into_json().await.expect("charge mode");
+    assert_eq!(charge_mode.until(), Some(until));
+    assert_eq!(charge_mode.duration(), Some(180));
+
+    // the mode now ends two hours later
+    let new_expiration = state.expiration.lock().await.unwrap();
+    let extension = new_expiration - expiration;
+    assert!(extension.abs_diff(Duration::from_secs(120 * 60)) < Duration::from_secs(2));
+    assert_eq!(state.control.lock().await.expires_at, Some(until));
+
+    // a new duration reports the resolved end as well
+    let response = client
+        .patch("/charge-mode")
+        .header(ContentType::JSON)
+        .body(json!({ "duration": 30 }).to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    let charge_mode: ChargeMode = response.into_json().await.expect("charge mode");
+    let started_at = state.control.lock().await.started_at.unwrap();
+    assert_eq!(
+        charge_mode.until(),
+        Some(started_at + chrono::Duration::minutes(30))
+    );
+    assert_eq!(state.control.lock().await.expires_at, charge_mode.until());
+}
+
+#[rocket::async_test]
+async fn test_patch_charge_mode_updates_active_loop() {
+    let ecos = MockEcos::start().await;
+    let client = create_mock_client(&ecos).await;
+    let payload = json!({ "mode": "active", "side_load": 0, "duration": 60 });
+    let response = client
+        .post("/charge-mode")
+        .header(ContentType::JSON)
+        .body(payload.to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    let posted = ecos.wait_for_posts(1).await;
+    assert_eq!(posted[0]["chargingList"][0]["power"], 500);
+
+    let response = client
+        .patch("/charge-mode")
+        .header(ContentType::JSON)
+        .body(json!({ "side_load": 200, "duration": 10 }).to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+
+    // the loop wakes up straight away instead of waiting for the check interval
+    let posted = ecos.wait_for_posts(2).await;
+    assert_eq!(posted[1]["chargingList"][0]["power"], 300);
+    let state = client.rocket().state::<Arc<AppState>>().unwrap();
+    let remaining = state
+        .expiration
+        .lock()
+        .await
+        .unwrap()
+        .saturating_duration_since(Instant::now());
+    assert!(remaining <= Duration::from_secs(10 * 60));
+}
+
+#[rocket::async_test]
+async fn test_patch_charge_mode_rejects_other_parameters() {
+    let ecos = MockEcos::start().await;
+    let client = create_mock_client(&ecos).await;
+
+    let response = client
+        .patch("/charge-mode")
+        .header(ContentType::JSON)
+        .body(json!({ "duration": 30 }).to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::UnprocessableEntity);
+    let error: ValidationError = response.into_json().await.expect("validation error");
+    assert_eq!(error.errors[0].field, "duration");
+
+    let response = client
+        .patch("/charge-mode")
+        .header(ContentType::JSON)
+        .body(json!({ "battery_level": 150 }).to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::UnprocessableEntity);
+}
+
+#[rocket::async_test]
+async fn test_post_charge_mode_until() {
+    let ecos = MockEcos::start().await;
+    let client = create_mock_client(&ecos).await;
+    let until = Local::now() + chrono::Duration::minutes(45);
+
+    let payload = json!({
+        "mode": "active",
+        "side_load": 0,
+        "until": until.to_rfc3339()
+    });
+    let response = client
+        .post("/charge-mode")
+        .header(ContentType::JSON)
+        .body(payload.to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+
+    // the current mode reports both the end and the duration rounded up to whole minutes
+    let state = client.rocket().state::<Arc<AppState>>().unwrap();
+    let current_mode = state.current_mode.lock().await.clone();
+    assert_eq!(current_mode.duration(), Some(45));
+    assert_eq!(
+        current_mode.until().map(|u| u.timestamp()),
+        Some(until.timestamp())
+    );
+    let remaining = state
+        .expiration
+        .lock()
+        .await
+        .unwrap()
+        .saturating_duration_since(Instant::now());
+    assert!(remaining > Duration::from_secs(44 * 60) && remaining <= Duration::from_secs(45 * 60));
+}
+
+#[rocket::async_test]
+async fn test_post_charge_mode_local_time_and_duration_report_both() {
+    let ecos = MockEcos::start().await;
+    let client = create_mock_client(&ecos).await;
+
+    let response = client
+        .post("/charge-mode")
+        .header(ContentType::JSON)
+        .body(json!({ "mode": "conservative", "battery_level": 80, "duration": 30 }).to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    let state = client.rocket().state::<Arc<AppState>>().unwrap();
+    let until = state.current_mode.lock().await.until().unwrap();
+    assert_eq!((until - Local::now()).num_minutes(), 29);
+
+    // a local time of day means its next occurrence, so it is never in the past
+    let end = (Local::now() + chrono::Duration::minutes(90)).format("%H:%M");
+    let response = client
+        .post("/charge-mode")
+        .header(ContentType::JSON)
+        .body(
+            json!({ "mode": "conservative", "battery_level": 80, "until": end.to_string() })
+                .to_string(),
+        )
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    let duration = state.current_mode.lock().await.duration().unwrap();
+    assert!((89..=90).contains(&duration));
+}
+
+#[rocket::async_test]
+async fn test_post_charge_mode_until_in_the_past() {
+    let until = Local::now() - chrono::Duration::minutes(5);
+    let error = post_invalid(
+        &json!({
+            "mode": "conservative",
+            "battery_level": 80,
+            "until": until.to_rfc3339()
+        })
+        .to_string(),
+    )
+    .await;
+    assert_eq!(error.errors[0].field, "until");
+
+    let error = post_invalid(
+        &json!({
+            "mode": "conservative",
+            "battery_level": 80,
+            "until": "half past five"
+        })
+        .to_string(),
+    )
+    .await;
+    assert_eq!(error.errors[0].field, "body");
+}
+
+/// Whether a charge schedule `window` ends at the minute of `until`
+fn assert_window_ends(window: &rocket::serde::json::Value, until: chrono::DateTime<Local>) {
+    assert_eq!(
+        (window["endHour"].as_u64(), window["endMinute"].as_u64()),
+        (Some(until.hour() as u64), Some(until.minute() as u64))
+    );
+}
+
+async fn wait_for_mode(state: &AppState, name: &str) {
+    for _ in 0..50 {
+        if state.current_mode.lock().await.name() == name {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("Expected the {} mode", name);
+}
+
+#[rocket::async_test]
+async fn test_force_charge_for_a_day() {
+    let ecos = MockEcos::start().await;
+    let client = create_mock_client(&ecos).await;
+
+    let payload =
+        json!({ "mode": "force-charge", "target_soc": 80, "power": 3000, "duration": 1440 });
+    let response = client
+        .post("/charge-mode")
+        .header(ContentType::JSON)
+        .body(payload.to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+
+    // the window stops a minute short of the day instead of ending at its start
+    let posted = ecos.wait_for_posts(1).await;
+    let window = &posted[0]["chargingList"][0];
+    let minutes = |hour: &str, minute: &str| {
+        window[hour].as_i64().unwrap() * 60 + window[minute].as_i64().unwrap()
+    };
+    let length =
+        (minutes("endHour", "endMinute") - minutes("startHour", "startMinute")).rem_euclid(24 * 60);
+    assert_eq!(length, 24 * 60 - 1);
+}
+
+#[rocket::async_test]
+async fn test_force_charge_stops_at_target_soc() {
+    let ecos = MockEcos::start().await;
+    let client = create_mock_client(&ecos).await;
+    let until = Local::now() + chrono::Duration::hours(2);
+
+    let payload = json!({
+        "mode": "force-charge",
+        "target_soc": 80,
+        "power": 3000,
+        "until": until.to_rfc3339()
+    });
+    let response = client
+        .post("/charge-mode")
+        .header(ContentType::JSON)
+        .body(payload.to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+
+    let posted = ecos.wait_for_posts(1).await;
+    assert_eq!(posted[0]["chargeUseMode"], 1);
+    let window = &posted[0]["chargingList"][0];
+    assert_eq!(window["power"], 3000);
+    assert_window_ends(window, until);
+
+    // wake the loop instead of waiting for the next SoC check
+    ecos.set_run_data(json!({ "batterySoc": 80.0 })).await;
+    let state = client.rocket().state::<Arc<AppState>>().unwrap();
+    state.mode_adjusted.notify_one();
+    wait_for_mode(state, "self-sufficient").await;
+    let posted = ecos.wait_for_posts(2).await;
+    assert_eq!(posted[1]["chargeUseMode"], 0);
+}
+
+#[rocket::async_test]
+async fn test_patch_force_charge_rewrites_window() {
+    let ecos = MockEcos::start().await;
+    let client = create_mock_client(&ecos).await;
+    let payload =
+        json!({ "mode": "force-charge", "target_soc": 80, "power": 3000, "duration": 60 });
+    let response = client
+        .post("/charge-mode")
+        .header(ContentType::JSON)
+        .body(payload.to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    ecos.wait_for_posts(1).await;
+
+    let response = client
+        .patch("/charge-mode")
+        .header(ContentType::JSON)
+        .body(json!({ "duration": 120, "power": 2000 }).to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+
+    // the new window lasts until the new end of the mode
+    let posted = ecos.wait_for_posts(2).await;
+    let window = &posted[1]["chargingList"][0];
+    assert_eq!(window["power"], 2000);
+    let state = client.rocket().state::<Arc<AppState>>().unwrap();
+    let until = state.current_mode.lock().await.until().unwrap();
+    assert_window_ends(window, until);
+}
+
+#[rocket::async_test]
+async fn test_force_charge_at_target_does_not_charge() {
+    let ecos = MockEcos::start().await;
+    ecos.set_run_data(json!({ "batterySoc": 95.0 })).await;
+    let client = create_mock_client(&ecos).await;
+
+    let payload = json!({
+        "mode": "force-charge",
+        "target_soc": 90,
+        "power": 2000,
+        "duration": 60
+    });
+    let response = client
+        .post("/charge-mode")
+        .header(ContentType::JSON)
+        .body(payload.to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+
+    let state = client.rocket().state::<Arc<AppState>>().unwrap();
+    wait_for_mode(state, "self-sufficient").await;
+    let posted = ecos.wait_for_posts(1).await;
+    assert!(posted
+        .iter()
+        .all(|p| p["chargingList"].as_array().unwrap().is_empty()));
+}
+
+#[rocket::async_test]
+async fn test_force_export_stops_at_floor_soc() {
+    let ecos = MockEcos::start().await;
+    ecos.set_run_data(json!({ "batterySoc": 90.0 })).await;
+    let client = create_mock_client(&ecos).await;
+
+    let payload = json!({
+        "mode": "force-export",
+        "power": 2500,
+        "floor_soc": 40,
+        "duration": 120
+    });
+    let response = client
+        .post("/charge-mode")
+        .header(ContentType::JSON)
+        .body(payload.to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+
+    let posted = ecos.wait_for_posts(1).await;
+    assert_eq!(posted[0]["dischargeToGridFlag"], 1);
+    assert_eq!(posted[0]["minCapacity"], 40);
+    assert_eq!(posted[0]["dischargingList"][0]["power"], 2500);
+
+    ecos.set_run_data(json!({ "batterySoc": 40.0 })).await;
+    let state = client.rocket().state::<Arc<AppState>>().unwrap();
+    state.mode_adjusted.notify_one();
+    wait_for_mode(state, "self-sufficient").await;
+    let posted = ecos.wait_for_posts(2).await;
+    assert_eq!(posted[1]["chargeUseMode"], 0);
+}
+
+#[rocket::async_test]
+async fn test_force_export_floor_below_eps_reserve() {
+    let error = post_invalid(
+        &json!({
+            "mode": "force-export",
+            "power": 2500,
+            "floor_soc": 0,
+            "duration": 60
+        })
+        .to_string(),
+    )
+    .await;
+    assert_eq!(error.errors.len(), 1);
+    assert_eq!(error.errors[0].field, "floor_soc");
+}
+
+#[rocket::async_test]
+async fn test_export_limit_charges_the_surplus() {
+    let ecos = MockEcos::start().await;
+    // exporting 3000 W through the meter while charging at 500 W
+    ecos.set_run_data(json!({ "meterPower": -3000.0, "batteryPower": 500.0 }))
+        .await;
+    let app_config = AppConfig {
+        ratedPower: Some(5000),
+        ..AppConfig::new()
+    };
+    let client = create_mock_client_with(&ecos, app_config).await;
+
+    let payload = json!({
+        "mode": "export-limit",
+        "max_export_w": 1000,
+        "duration": 60,
+        "check_interval": 300
+    });
+    let response = client
+        .post("/charge-mode")
+        .header(ContentType::JSON)
+        .body(payload.to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+
+    let posted = ecos.wait_for_posts(1).await;
+    assert_eq!(posted[0]["chargeUseMode"], 1);
+    assert_eq!(posted[0]["chargingList"][0]["power"], 2500);
+    assert_eq!(posted[0]["maxFeedIn"], 20);
+
+    // under the limit: no extra charging
+    ecos.set_run_data(json!({ "meterPower": -800.0, "batteryPower": 0.0 }))
+        .await;
+    let adjustment = json!({ "max_export_w": 1500 });
+    let response = client
+        .patch("/charge-mode")
+        .header(ContentType::JSON)
+        .body(adjustment.to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    let posted = ecos.wait_for_posts(2).await;
+    assert!(posted[1]["chargingList"].as_array().unwrap().is_empty());
+    assert_eq!(posted[1]["maxFeedIn"], 30);
+}
+
+#[rocket::async_test]
+async fn test_peak_shave_discharges_over_the_cap() {
+    let ecos = MockEcos::start().await;
+    ecos.set_run_data(json!({
+        "homePower": 3000.0,
+        "meterPower": 2500.0,
+        "batteryPower": 0.0,
+        "batterySoc": 60.0
+    }))
+    .await;
+    let client = create_mock_client(&ecos).await;
+
+    let payload = json!({
+        "mode": "peak-shave",
+        "import_cap_w": 1000,
+        "reserve_soc": 30,
+        "duration": 120
+    });
+    let response = client
+        .post("/charge-mode")
+        .header(ContentType::JSON)
+        .body(payload.to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+
+    let posted = ecos.wait_for_posts(1).await;
+    assert_eq!(posted[0]["minCapacity"], 30);
+    assert_eq!(posted[0]["dischargingList"][0]["power"], 1500);
+
+    // at the reserve the battery is left alone
+    ecos.set_run_data(json!({ "batterySoc": 30.0 })).await;
+    let state = client.rocket().state::<Arc<AppState>>().unwrap();
+    state.mode_adjusted.notify_one();
+    let posted = ecos.wait_for_posts(2).await;
+    assert!(posted[1]["dischargingList"].as_array().unwrap().is_empty());
+}
+
+#[rocket::async_test]
+async fn test_controller_limits_the_power_step() {
+    let ecos = MockEcos::start().await;
+    ecos.set_run_data(json!({ "meterPower": -3000.0, "batteryPower": 500.0 }))
+        .await;
+    let client = create_mock_client(&ecos).await;
+
+    let payload = json!({
+        "mode": "export-limit",
+        "max_export_w": 1000,
+        "duration": 60,
+        "controller": { "max_step": 1000 }
+    });
+    let response = client
+        .post("/charge-mode")
+        .header(ContentType::JSON)
+        .body(payload.to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    let posted = ecos.wait_for_posts(1).await;
+    assert_eq!(posted[0]["chargingList"][0]["power"], 1000);
+
+    // the next check continues from the applied power
+    let state = client.rocket().state::<Arc<AppState>>().unwrap();
+    state.mode_adjusted.notify_one();
+    let posted = ecos.wait_for_posts(2).await;
+    assert_eq!(posted[1]["chargingList"][0]["power"], 2000);
+
+    let error = post_invalid(
+        &json!({
+            "mode": "active",
+            "side_load": 0,
+            "duration": 60,
+            "controller": { "smoothing": 2.0 }
+        })
+        .to_string(),
+    )
+    .await;
+    assert_eq!(error.errors[0].field, "controller.smoothing");
+}
+
+#[rocket::async_test]
+async fn test_hysteresis_skips_small_changes() {
+    let ecos = MockEcos::start().await;
+    ecos.set_run_data(json!({ "meterPower": -3000.0, "batteryPower": 0.0 }))
+        .await;
+    let app_config = AppConfig {
+        hysteresis: HysteresisConfig {
+            power: 200,
+            hold: 600,
+        },
+        ..AppConfig::new()
+    };
+    let client = create_mock_client_with(&ecos, app_config).await;
+
+    let payload = json!({
+        "mode": "export-limit",
+        "max_export_w": 1000,
+        "duration": 60,
+        "check_interval": 300
+    });
+    let response = client
+        .post("/charge-mode")
+        .header(ContentType::JSON)
+        .body(payload.to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    let posted = ecos.wait_for_posts(1).await;
+    let window = &posted[0]["chargingList"][0];
+    assert_eq!(window["power"], 2000);
+    // the window lasts for the check interval and the hold time
+    let minutes = |hour: &serde_json::Value, minute: &serde_json::Value| {
+        hour.as_i64().unwrap() * 60 + minute.as_i64().unwrap()
+    };
+    let length = (minutes(&window["endHour"], &window["endMinute"])
+        - minutes(&window["startHour"], &window["startMinute"]))
+    .rem_euclid(24 * 60);
+    assert_eq!(length, 15);
+
+    // 100 W more is within the hysteresis
+    ecos.set_run_data(json!({ "meterPower": -3100.0 })).await;
+    let state = client.rocket().state::<Arc<AppState>>().unwrap();
+    state.mode_adjusted.notify_one();
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert_eq!(ecos.posted().await.len(), 1);
+
+    // 500 W more is not
+    ecos.set_run_data(json!({ "meterPower": -3500.0 })).await;
+    state.mode_adjusted.notify_one();
+    let posted = ecos.wait_for_posts(2).await;
+    assert_eq!(posted[1]["chargingList"][0]["power"], 2500);
+}
+
+#[rocket::async_test]
+async fn test_rejected_settings_are_written_again() {
+    let ecos = MockEcos::start().await;
+    ecos.set_run_data(json!({ "meterPower": -3000.0, "batteryPower": 0.0 }))
+        .await;
+    ecos.reject_posts(true).await;
+    let app_config = AppConfig {
+        hysteresis: HysteresisConfig {
+            power: 200,
+            hold: 600,
+        },
+        ..AppConfig::new()
+    };
+    let client = create_mock_client_with(&ecos, app_config).await;
+
+    let payload = json!({
+        "mode": "export-limit",
+        "max_export_w": 1000,
+        "duration": 60,
+        "check_interval": 300
+    });
+    let response = client
+        .post("/charge-mode")
+        .header(ContentType::JSON)
+        .body(payload.to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    ecos.wait_for_posts(1).await;
+    let state = client.rocket().state::<Arc<AppState>>().unwrap();
+    assert!(state.control.lock().await.settings.is_none());
+
+    // the same settings are not within the hysteresis of settings the device rejected
+    ecos.reject_posts(false).await;
+    state.mode_adjusted.notify_one();
+    let posted = ecos.wait_for_posts(2).await;
+    assert_eq!(posted[1]["chargingList"][0]["power"], 2000);
+    assert_eq!(ecos.settings().await["chargingList"][0]["power"], 2000);
+}
+
+#[rocket::async_test]
+async fn test_preserve_settings_restores_the_original() {
+    let ecos = MockEcos::start().await;
+    let window = json!({
+        "startHour": 1, "startMinute": 0, "endHour": 5, "endMinute": 0,
+        "power": 1500, "abandonPv": 0
+    });
+    ecos.set_settings(json!({
+        "chargeUseMode": 1,
+        "minCapacity": 25,
+        "maxFeedIn": 50,
+        "epsBatteryMin": 20,
+        "chargingList": [window]
+    }))
+    .await;
+    let app_config = AppConfig {
+        preserveSettings: true,
+        ..AppConfig::new()
+    };
+    let client = create_mock_client_with(&ecos, app_config).await;
+
+    let payload = json!({ "mode": "conservative", "battery_level": 80, "duration": 60 });
+    let response = client
+        .post("/charge-mode")
+        .header(ContentType::JSON)
+        .body(payload.to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+
+    // only the fields of the mode change
+    let posted = ecos.wait_for_posts(1).await;
+    assert_eq!(posted[0]["chargeUseMode"], 0);
+    assert_eq!(posted[0]["minCapacity"], 80);
+    assert_eq!(posted[0]["maxFeedIn"], 50);
+    assert_eq!(posted[0]["epsBatteryMin"], 20);
+    assert_eq!(posted[0]["chargingList"][0]["power"], 1500);
+
+    // a following mode builds on the same original settings
+    let payload =
+        json!({ "mode": "force-charge", "target_soc": 90, "power": 2000, "duration": 60 });
+    let response = client
+        .post("/charge-mode")
+        .header(ContentType::JSON)
+        .body(payload.to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    let posted = ecos.wait_for_posts(2).await;
+    assert_eq!(posted[1]["minCapacity"], 25);
+    assert_eq!(posted[1]["maxFeedIn"], 50);
+    let windows = posted[1]["chargingList"].as_array().unwrap();
+    assert_eq!(windows.len(), 1);
+    assert_eq!(windows[0]["power"], 2000);
+
+    let state = client.rocket().state::<Arc<AppState>>().unwrap();
+    state.reset_mode(Actor::controller("test")).await;
+    let posted = ecos.wait_for_posts(3).await;
+    assert_eq!(posted[2]["chargeUseMode"], 1);
+    assert_eq!(posted[2]["minCapacity"], 25);
+    assert_eq!(posted[2]["chargingList"][0]["power"], 1500);
+    assert_eq!(posted[2]["chargingList"][0]["endHour"], 5);
+}
+
+#[rocket::async_test]
+async fn test_preserve_settings_not_kept_by_self_sufficient() {
+    let ecos = MockEcos::start().await;
+    ecos.set_settings(json!({ "maxFeedIn": 50 })).await;
+    let app_config = AppConfig {
+        preserveSettings: true,
+        ..AppConfig::new()
+    };
+    let client = create_mock_client_with(&ecos, app_config).await;
+    let state = client.rocket().state::<Arc<AppState>>().unwrap();
+
+    let payloads = [
+        json!({ "mode": "conservative", "battery_level": 80, "duration": 60 }),
+        json!({ "mode": "self-sufficient", "battery_level": 10 }),
+    ];
+    for (i, payload) in payloads.iter().enumerate() {
+        let response = client
+            .post("/charge-mode")
+            .header(ContentType::JSON)
+            .body(payload.to_string())
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        ecos.wait_for_posts(i + 1).await;
+    }
+    // self-sufficient restores the settings from before conservative and forgets them
+    let posted = ecos.posted().await;
+    assert_eq!(posted[1]["maxFeedIn"], 50);
+    assert!(state.preserved_settings.lock().await.is_none());
+
+    // a change in the vendor app while self-sufficient
+    ecos.set_settings(json!({ "maxFeedIn": 40 })).await;
+
+    let payload = json!({ "mode": "conservative", "battery_level": 80, "duration": 60 });
+    let response = client
+        .post("/charge-mode")
+        .header(ContentType::JSON)
+        .body(payload.to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    ecos.wait_for_posts(3).await;
+
+    // run the mode to its expiry
+    *state.expiration.lock().await = Some(Instant::now());
+    state.mode_adjusted.notify_one();
+    let posted = ecos.wait_for_posts(5).await;
+    let restored = posted.last().unwrap();
+    assert_eq!(restored["chargeUseMode"], 0);
+    assert_eq!(restored["minCapacity"], 10);
+    assert_eq!(restored["maxFeedIn"], 40);
+}
+
+#[rocket::async_test]
+async fn test_preserve_settings_reads_the_device_uncached() {
+    let ecos = MockEcos::start().await;
+    let app_config = AppConfig {
+        preserveSettings: true,
+        ..AppConfig::new()
+    };
+    let client = create_mock_client_with(&ecos, app_config).await;
+    let state = client.rocket().state::<Arc<AppState>>().unwrap();
+
+    // a change in the vendor app while the old settings are cached
+    state
+        .ecos_client
+        .get_charge_mode_settings_cached("123456")
+        .await
+        .expect("settings");
+    ecos.set_settings(json!({ "maxFeedIn": 40 })).await;
+
+    let payload = json!({ "mode": "conservative", "battery_level": 80, "duration": 60 });
+    let response = client
+        .post("/charge-mode")
+        .header(ContentType::JSON)
+        .body(payload.to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    ecos.wait_for_posts(1).await;
+    state.reset_mode(Actor::controller("test")).await;
+    let posted = ecos.wait_for_posts(2).await;
+    assert_eq!(posted[1]["maxFeedIn"], 40);
+}
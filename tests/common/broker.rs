@@ -0,0 +1,158 @@
+//! A minimal MQTT 3.1.1 broker for the tests: QoS 0/1 publish, subscribe with wildcards and retained messages.
+use bytes::BytesMut;
+use rocket::tokio;
+use rocket::tokio::io::{AsyncReadExt, AsyncWriteExt};
+use rocket::tokio::net::{TcpListener, TcpStream};
+use rocket::tokio::sync::{broadcast, Mutex};
+use rumqttc::mqttbytes::v4::{
+    read, ConnAck, ConnectReturnCode, Packet, PingResp, PubAck, Publish, SubAck,
+    SubscribeReasonCode,
+};
+use rumqttc::mqttbytes::{matches, Error, QoS};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+pub struct Broker {
+    pub port: u16,
+    published: Arc<Mutex<Vec<Publish>>>,
+    retained: Arc<Mutex<HashMap<String, Publish>>>,
+    sender: broadcast::Sender<Publish>,
+}
+
+impl Broker {
+    pub async fn start() -> Broker {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind broker");
+        let broker = Broker {
+            port: listener.local_addr().unwrap().port(),
+            published: Arc::new(Mutex::new(vec![])),
+            retained: Arc::new(Mutex::new(HashMap::new())),
+            sender: broadcast::channel(256).0,
+        };
+        let (published, retained, sender) = (
+            broker.published.clone(),
+            broker.retained.clone(),
+            broker.sender.clone(),
+        );
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(handle_connection(
+                    stream,
+                    published.clone(),
+                    retained.clone(),
+                    sender.clone(),
+                ));
+            }
+        });
+        broker
+    }
+
+    /// Publish a message to all subscribers, as if another client sent it
+    pub async fn publish(&self, topic: &str, payload: &str) {
+        let publish = Publish::new(topic, QoS::AtMostOnce, payload.as_bytes().to_vec());
+        self.published.lock().await.push(publish.clone());
+        let _ = self.sender.send(publish);
+    }
+
+    /// Wait until a message has been published to `topic` and return the latest one
+    pub async fn wait_for(&self, topic: &str) -> Publish {
+        for _ in 0..100 {
+            if let Some(p) = self.messages(topic).await.pop() {
+                return p;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        panic!("No message published to {}", topic);
+    }
+
+    /// All messages published to `topic` so far
+    pub async fn messages(&self, topic: &str) -> Vec<Publish> {
+        self.published
+            .lock()
+            .await
+            .iter()
+            .filter(|p| p.topic == topic)
+            .cloned()
+            .collect()
+    }
+}
+
+async fn handle_connection(
+    mut stream: TcpStream,
+    published: Arc<Mutex<Vec<Publish>>>,
+    retained: Arc<Mutex<HashMap<String, Publish>>>,
+    sender: broadcast::Sender<Publish>,
+) {
+    let mut receiver = sender.subscribe();
+    let mut filters: Vec<String> = vec![];
+    let mut input = BytesMut::new();
+    loop {
+        let mut output = BytesMut::new();
+        tokio::select! {
+            n = stream.read_buf(&mut input) => {
+                if !matches!(n, Ok(n) if n > 0) {
+                    return;
+                }
+                loop {
+                    let packet = match read(&mut input, 1024 * 1024) {
+                        Ok(packet) => packet,
+                        Err(Error::InsufficientBytes(_)) => break,
+                        Err(_) => return,
+                    };
+                    match packet {
+                        Packet::Connect(_) => {
+                            ConnAck::new(ConnectReturnCode::Success, false).write(&mut output).unwrap();
+                        }
+                        Packet::Subscribe(subscribe) => {
+                            let codes = subscribe
+                                .filters
+                                .iter()
+                                .map(|f| SubscribeReasonCode::Success(f.qos))
+                                .collect();
+                            SubAck::new(subscribe.pkid, codes).write(&mut output).unwrap();
+                            for filter in subscribe.filters {
+                                for publish in retained.lock().await.values() {
+                                    if matches(&publish.topic, &filter.path) {
+                                        forward(publish, &mut output);
+                                    }
+                                }
+                                filters.push(filter.path);
+                            }
+                        }
+                        Packet::Publish(publish) => {
+                            if publish.qos != QoS::AtMostOnce {
+                                PubAck::new(publish.pkid).write(&mut output).unwrap();
+                            }
+                            if publish.retain {
+                                retained.lock().await.insert(publish.topic.clone(), publish.clone());
+                            }
+                            published.lock().await.push(publish.clone());
+                            let _ = sender.send(publish);
+                        }
+                        Packet::PingReq => {
+                            PingResp.write(&mut output).unwrap();
+                        }
+                        Packet::Disconnect => return,
+                        _ => {}
+                    }
+                }
+            }
+            publish = receiver.recv() => {
+                if let Ok(publish) = publish {
+                    if filters.iter().any(|f| matches(&publish.topic, f)) {
+                        forward(&publish, &mut output);
+                    }
+                }
+            }
+        }
+        if !output.is_empty() && stream.write_all(&output).await.is_err() {
+            return;
+        }
+    }
+}
+
+fn forward(publish: &Publish, output: &mut BytesMut) {
+    let mut publish = Publish::new(&publish.topic, QoS::AtMostOnce, publish.payload.to_vec());
+    publish.retain = false;
+    publish.write(output).unwrap();
+}
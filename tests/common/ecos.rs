@@ -0,0 +1,246 @@
+//! A mock ECOS API for the tests: login, device list, run data and charge mode settings.
+//! Posted settings are merged into the current ones, and requests are counted per path.
+use base64::engine::general_purpose;
+use base64::Engine;
+use rocket::serde::json::{json, serde_json, Value};
+use rocket::tokio;
+use rocket::tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use rocket::tokio::net::{TcpListener, TcpStream};
+use rocket::tokio::sync::Mutex;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Default)]
+pub struct EcosState {
+    pub run_data: Value,
+    pub settings: Value,
+    pub posted: Vec<Value>,
+    /// Answer settings posts with `"success": false` and leave the settings unchanged
+    pub reject_posts: bool,
+    pub requests: HashMap<String, usize>,
+}
+
+pub struct MockEcos {
+    pub base_url: String,
+    pub state: Arc<Mutex<EcosState>>,
+}
+
+impl MockEcos {
+    pub async fn start() -> MockEcos {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind ECOS");
+        let port = listener.local_addr().unwrap().port();
+        let state = Arc::new(Mutex::new(EcosState {
+            run_data: default_run_data(),
+            settings: default_settings(),
+            ..EcosState::default()
+        }));
+        let shared = state.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(handle_connection(stream, shared.clone()));
+            }
+        });
+        MockEcos {
+            base_url: format!("http://127.0.0.1:{}/api", port),
+            state,
+        }
+    }
+
+    /// The number of requests received for a path, e.g. `/api/client/home/device/list`
+    pub async fn requests(&self, path: &str) -> usize {
+        *self.state.lock().await.requests.get(path).unwrap_or(&0)
+    }
+
+    pub async fn set_run_data(&self, fields: Value) {
+        merge(&mut self.state.lock().await.run_data, &fields);
+    }
+
+    pub async fn set_settings(&self, fields: Value) {
+        merge(&mut self.state.lock().await.settings, &fields);
+    }
+
+    pub async fn reject_posts(&self, reject: bool) {
+        self.state.lock().await.reject_posts = reject;
+    }
+
+    pub async fn settings(&self) -> Value {
+        self.state.lock().await.settings.clone()
+    }
+
+    /// The bodies of all charge mode settings posts
+    pub async fn posted(&self) -> Vec<Value> {
+        self.state.lock().await.posted.clone()
+    }
+
+    /// Wait up to 2.5 s for at least `count` settings posts, for modes that post from a background task
+    pub async fn wait_for_posts(&self, count: usize) -> Vec<Value> {
+        for _ in 0..50 {
+            let posted = self.posted().await;
+            if posted.len() >= count {
+                return posted;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        panic!("Expected {} settings posts", count);
+    }
+}
+
+fn default_run_data() -> Value {
+    json!({
+        "batterySoc": 50.0,
+        "batteryPower": 0.0,
+        "epsPower": 0.0,
+        "gridPower": 0.0,
+        "homePower": 500.0,
+        "meterPower": 0.0,
+        "solarPower": 500.0,
+        "sysRunMode": 1,
+        "isExistSolar": true,
+        "sysPowerConfig": 3,
+    })
+}
+
+fn default_settings() -> Value {
+    json!({
+        "minCapacity": 10,
+        "chargeUseMode": 0,
+        "maxFeedIn": 100,
+        "epsBatteryMin": 10,
+        "dischargeToGridFlag": 0,
+        "selfSoc": 10,
+        "selfEpsBat": 10,
+        "selfFeedIn": 100,
+        "regularSoc": 10,
+        "regularEpsBat": 10,
+        "regularFeedIn": 100,
+        "backupSoc": 30,
+        "backupEpsBat": 30,
+        "backupFeedIn": 100,
+        "emsSoftwareVersion": "1.0",
+        "dsp1SoftwareVersion": "1.0",
+        "ratedPower": "5000",
+        "region": "AU",
+        "autoStrategy": 0,
+        "chargingList": [],
+        "dischargingList": [],
+    })
+}
+
+fn merge(target: &mut Value, fields: &Value) {
+    if let (Some(target), Some(fields)) = (target.as_object_mut(), fields.as_object()) {
+        for (key, value) in fields {
+            target.insert(key.clone(), value.clone());
+        }
+    }
+}
+
+fn token() -> String {
+    // expires in 2100
+    let claims = general_purpose::STANDARD_NO_PAD.encode(r#"{"exp":4102444800}"#);
+    format!("header.{}.signature", claims)
+}
+
+fn ok(data: Value) -> Value {
+    json!({ "code": 0, "message": "success", "success": true, "data": data })
+}
+
+async fn respond(state: &Mutex<EcosState>, method: &str, path: &str, body: &[u8]) -> Value {
+    let mut state = state.lock().await;
+    *state.requests.entry(path.to_string()).or_default() += 1;
+    match (method, path) {
+        ("POST", "/api/client/guide/login") => {
+            ok(json!({ "accessToken": token(), "refreshToken": token() }))
+        }
+        ("GET", "/api/client/home/device/list") => ok(json!([{
+            "deviceId": "123456",
+            "deviceAliasName": "Home",
+            "wifiSn": "WIFI",
+            "state": 1,
+            "weight": 0,
+            "temp": null,
+            "icon": null,
+            "vpp": false,
+            "master": 1,
+            "deviceSn": "SN",
+            "agentId": "agent",
+            "lon": 0.0,
+            "lat": 0.0,
+            "category": null,
+            "model": null,
+            "deviceType": null,
+        }])),
+        ("POST", "/api/client/home/now/device/runData") => ok(state.run_data.clone()),
+        ("GET", "/api/client/customize/info") => ok(state.settings.clone()),
+        ("POST", "/api/client/customize/info") => {
+            let posted: Value = serde_json::from_slice(body).unwrap_or_default();
+            if state.reject_posts {
+                state.posted.push(posted);
+                return json!({ "code": 500, "message": "rejected", "success": false });
+            }
+            let known: Vec<String> = state
+                .settings
+                .as_object()
+                .map(|s| s.keys().cloned().collect())
+                .unwrap_or_default();
+            for key in known {
+                if let Some(value) = posted.get(&key) {
+                    state.settings[&key] = value.clone();
+                }
+            }
+            state.posted.push(posted);
+            json!({ "code": 0, "message": "success", "success": true })
+        }
+        _ => json!({ "code": 404, "message": "not found", "success": false }),
+    }
+}
+
+async fn handle_connection(stream: TcpStream, state: Arc<Mutex<EcosState>>) {
+    let mut reader = BufReader::new(stream);
+    loop {
+        let mut request_line = String::new();
+        if !matches!(reader.read_line(&mut request_line).await, Ok(n) if n > 0) {
+            return;
+        }
+        let mut parts = request_line.split_whitespace();
+        let method = parts.next().unwrap_or_default().to_string();
+        let target = parts.next().unwrap_or_default();
+        let path = target.split('?').next().unwrap_or_default().to_string();
+
+        let mut content_length = 0;
+        loop {
+            let mut line = String::new();
+            if !matches!(reader.read_line(&mut line).await, Ok(n) if n > 0) {
+                return;
+            }
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse().unwrap_or(0);
+                }
+            }
+        }
+        let mut body = vec![0; content_length];
+        if reader.read_exact(&mut body).await.is_err() {
+            return;
+        }
+
+        let response = respond(&state, &method, &path, &body).await.to_string();
+        let message = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            response.len(),
+            response
+        );
+        if reader
+            .get_mut()
+            .write_all(message.as_bytes())
+            .await
+            .is_err()
+        {
+            return;
+        }
+    }
+}
@@ -0,0 +1,86 @@
+mod common;
+
+use common::ecos::MockEcos;
+use ecactus_controller::audit::{AuditEvent, AuditKind};
+use ecactus_controller::config::{AppConfig, CacheConfig};
+use ecactus_controller::ecos::client::EcosClient;
+use ecactus_controller::routes;
+use ecactus_controller::state::AppState;
+use rocket::http::Status;
+use rocket::local::asynchronous::Client;
+use rocket::serde::json::Value;
+use std::path::Path;
+use std::sync::Arc;
+
+fn write_config(path: &Path, app: &str) {
+    let content = format!(
+        "[ecos]\nuser = \"user\"\npassword = \"password\"\nbase_url = \"http://localhost\"\n\n[app]\n{}",
+        app
+    );
+    std::fs::write(path, content).expect("write config");
+}
+
+#[rocket::async_test]
+async fn test_reload_config() {
+    let ecos = MockEcos::start().await;
+    let dir = tempfile::tempdir().expect("temp dir");
+    let config_path = dir.path().join("config.toml");
+    let app_state = Arc::new(AppState {
+        config_path: Some(config_path.clone()),
+        ..AppState::new(
+            AppConfig::new(),
+            Arc::new(
+                EcosClient::new(
+                    "user".to_string(),
+                    "password".to_string(),
+                    ecos.base_url.clone(),
+                )
+                .with_cache(&CacheConfig::default()),
+            ),
+        )
+    });
+    let rocket = rocket::build()
+        .manage(app_state.clone())
+        .mount("/", routes::config::routes())
+        .mount("/", routes::charge_mode::routes());
+    let client = Client::tracked(rocket)
+        .await
+        .expect("valid rocket instance");
+
+    write_config(&config_path, "checkInterval = 300\nminCapacity = 20\n");
+    let response = client.post("/config/reload").dispatch().await;
+    assert_eq!(response.status(), Status::NoContent);
+    assert_eq!(app_state.config().checkInterval, 300);
+
+    // the next settings write uses the new config
+    let response = client.put("/charge-mode/reset").dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(ecos.posted().await[0]["minCapacity"], 20);
+
+    write_config(&config_path, "checkInterval = 10\nmaxFeedIn = 150\n");
+    let response = client.post("/config/reload").dispatch().await;
+    assert_eq!(response.status(), Status::UnprocessableEntity);
+    let body: Value = response.into_json().await.expect("validation error");
+    assert_eq!(body["errors"][0]["field"], "checkInterval");
+    assert_eq!(body["errors"][1]["field"], "maxFeedIn");
+    assert_eq!(app_state.config().minCapacity, 20);
+
+    write_config(&config_path, "minCapacity = \"high\"\n");
+    let response = client.post("/config/reload").dispatch().await;
+    assert_eq!(response.status(), Status::UnprocessableEntity);
+    let body: Value = response.into_json().await.expect("validation error");
+    assert_eq!(body["errors"][0]["field"], "file");
+
+    let entries = app_state.audit.read_all().await.expect("audit log");
+    let reloads: Vec<&Option<String>> = entries
+        .iter()
+        .filter(|entry| entry.event.kind() == AuditKind::ConfigReload)
+        .map(|entry| match &entry.event {
+            AuditEvent::ConfigReload { error } => error,
+            _ => unreachable!(),
+        })
+        .collect();
+    assert_eq!(reloads.len(), 3);
+    assert!(reloads[0].is_none());
+    assert!(reloads[1].as_ref().unwrap().contains("checkInterval"));
+}
@@ -0,0 +1,197 @@
+mod common;
+
+use common::ecos::MockEcos;
+use ecactus_controller::config::{read_config, AppConfig, CacheConfig};
+use ecactus_controller::ecos::client::EcosClient;
+use ecactus_controller::routes;
+use ecactus_controller::state::AppState;
+use rocket::http::{ContentType, Status};
+use rocket::local::asynchronous::Client;
+use rocket::serde::json::{json, serde_json, Value};
+use std::sync::Arc;
+
+const RUN_DATA: &str = "/api/client/home/now/device/runData";
+const SETTINGS: &str = "/api/client/customize/info";
+
+fn ecos_client(ecos: &MockEcos, cache: CacheConfig) -> Arc<EcosClient> {
+    Arc::new(
+        EcosClient::new(
+            "user".to_string(),
+            "password".to_string(),
+            ecos.base_url.clone(),
+        )
+        .with_cache(&cache),
+    )
+}
+
+#[rocket::async_test]
+async fn test_run_data_is_cached() {
+    let ecos = MockEcos::start().await;
+    let app_state = Arc::new(AppState::new(
+        AppConfig::new(),
+        ecos_client(&ecos, CacheConfig::default()),
+    ));
+    let rocket = rocket::build()
+        .manage(app_state.clone())
+        .mount("/ecos", routes::ecos::routes());
+    let client = Client::tracked(rocket)
+        .await
+        .expect("valid rocket instance");
+
+    for _ in 0..3 {
+        let response = client.get("/ecos/run-data").dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        assert!(response.headers().get_one("Age").is_some());
+        let body: Value = response.into_json().await.expect("run data");
+        assert_eq!(body["data"]["batterySoc"], 50.0);
+    }
+    // the controller shares the cache with the API
+    app_state
+        .ecos_client
+        .get_run_data(app_state.config().deviceId.clone())
+        .await
+        .expect("run data");
+    assert_eq!(ecos.requests(RUN_DATA).await, 1);
+}
+
+#[rocket::async_test]
+async fn test_post_invalidates_cached_settings() {
+    let ecos = MockEcos::start().await;
+    let app_state = AppState::new(AppConfig::new(), ecos_client(&ecos, CacheConfig::default()));
+    let device_id = app_state.config().deviceId.clone();
+
+    ecos.set_settings(json!({ "minCapacity": 30 })).await;
+    let settings = app_state
+        .ecos_client
+        .get_charge_mode_settings(&device_id)
+        .await
+        .expect("settings");
+    assert_eq!(settings.data.minCapacity, 30);
+
+    app_state.update_charge_mode(0, Some(60), None, None).await;
+    let cached = app_state
+        .ecos_client
+        .get_charge_mode_settings_cached(&device_id)
+        .await
+        .expect("settings");
+    assert_eq!(cached.value.data.minCapacity, 60);
+    assert_eq!(ecos.requests(SETTINGS).await, 3);
+}
+
+#[rocket::async_test]
+async fn test_zero_ttl_disables_cache() {
+    let ecos = MockEcos::start().await;
+    let client = ecos_client(
+        &ecos,
+        CacheConfig {
+            run_data: 0,
+            ..CacheConfig::default()
+        },
+    );
+    for _ in 0..2 {
+        client
+            .get_run_data("123456".to_string())
+            .await
+            .expect("run data");
+    }
+    assert_eq!(ecos.requests(RUN_DATA).await, 2);
+}
+
+#[rocket::async_test]
+async fn test_put_settings_merges_and_persists() {
+    let ecos = MockEcos::start().await;
+    let dir = tempfile::tempdir().expect("temp dir");
+    let config_path = dir.path().join("config.toml");
+    std::fs::write(
+        &config_path,
+        "[ecos]\nuser = \"user\"\npassword = \"password\"\nbase_url = \"http://localhost\"\n\n\
+         [app]\n# the export limit\nmaxFeedIn = 100\nchargingList = []\n",
+    )
+    .expect("write config");
+    let app_state = Arc::new(AppState {
+        config_path: Some(config_path.clone()),
+        ..AppState::new(AppConfig::new(), ecos_client(&ecos, CacheConfig::default()))
+    });
+    let rocket = rocket::build()
+        .manage(app_state.clone())
+        .mount("/ecos", routes::ecos::routes());
+    let client = Client::tracked(rocket)
+        .await
+        .expect("valid rocket instance");
+
+    // a change in the vendor app while the old settings are cached
+    app_state
+        .ecos_client
+        .get_charge_mode_settings_cached("123456")
+        .await
+        .expect("settings");
+    ecos.set_settings(json!({ "maxFeedIn": 80, "epsBatteryMin": 5 }))
+        .await;
+
+    let response = client
+        .put("/ecos/charge-mode-settings?persist=true")
+        .header(ContentType::JSON)
+        .body(
+            json!({
+                "maxFeedIn": 50,
+                "chargingList": [{ "startHour": 1, "startMinute": 0, "endHour": 5, "endMinute": 30, "power": 3000, "abandonPv": 0 }]
+            })
+            .to_string(),
+        )
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    let posted = ecos.posted().await;
+    assert_eq!(posted.len(), 1);
+    assert_eq!(posted[0]["maxFeedIn"], 50);
+    // fields missing from the request keep the device values
+    assert_eq!(posted[0]["epsBatteryMin"], 5);
+    assert_eq!(posted[0]["chargingList"][0]["endMinute"], 30);
+
+    let content = std::fs::read_to_string(&config_path).expect("read config");
+    assert!(content.contains("# the export limit"));
+    let config: Value = read_config(config_path.to_str().unwrap());
+    let app_config: AppConfig = serde_json::from_value(config["app"].clone()).expect("app config");
+    assert_eq!(app_config.maxFeedIn, 50);
+    assert_eq!(app_config.chargingList[0].power, 3000);
+    assert_eq!(app_config.epsBatteryMin, AppConfig::new().epsBatteryMin);
+    // and the running config reloaded
+    assert_eq!(app_state.config().maxFeedIn, 50);
+
+    // defaults that would make the config invalid are rejected before anything is written
+    let response = client
+        .put("/ecos/charge-mode-settings?persist=true")
+        .header(ContentType::JSON)
+        .body(json!({ "minCapacity": 5 }).to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::UnprocessableEntity);
+    let body: Value = response.into_json().await.expect("validation error");
+    assert_eq!(body["errors"][0]["field"], "minCapacity");
+    let response = client
+        .put("/ecos/charge-mode-settings?persist=true&device_id=654321")
+        .header(ContentType::JSON)
+        .body(json!({ "maxFeedIn": 60 }).to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::UnprocessableEntity);
+    let body: Value = response.into_json().await.expect("validation error");
+    assert_eq!(body["errors"][0]["field"], "persist");
+    assert_eq!(ecos.posted().await.len(), 1);
+    assert_eq!(
+        std::fs::read_to_string(&config_path).expect("read config"),
+        content
+    );
+
+    let response = client
+        .put("/ecos/charge-mode-settings")
+        .header(ContentType::JSON)
+        .body(json!({ "minCapacity": 3, "maxFeedIn": 120 }).to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::UnprocessableEntity);
+    let body: Value = response.into_json().await.expect("validation error");
+    assert_eq!(body["errors"][0]["field"], "minCapacity");
+    assert_eq!(body["errors"][1]["field"], "maxFeedIn");
+    assert_eq!(ecos.posted().await.len(), 1);
+}
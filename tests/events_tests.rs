@@ -0,0 +1,100 @@
+use chrono::Local;
+use ecactus_controller::config::AppConfig;
+use ecactus_controller::ecos::client::EcosClient;
+use ecactus_controller::ecos::data_models::RunData;
+use ecactus_controller::events::ControllerEvent;
+use ecactus_controller::history::RunDataSample;
+use ecactus_controller::routes;
+use ecactus_controller::state::{AppState, ChargeMode};
+use rocket::http::{ContentType, Status};
+use rocket::local::asynchronous::Client;
+use rocket::serde::json::{json, serde_json};
+use std::sync::Arc;
+
+fn run_data() -> RunData {
+    RunData {
+        batterySoc: 42.0,
+        batteryPower: 0.0,
+        epsPower: 0.0,
+        gridPower: 0.0,
+        homePower: 300.0,
+        meterPower: 0.0,
+        solarPower: 900.0,
+        sysRunMode: 1,
+        isExistSolar: true,
+        sysPowerConfig: 3,
+    }
+}
+
+async fn create_client() -> Client {
+    let app_state = Arc::new(AppState::new(
+        AppConfig::new(),
+        Arc::new(EcosClient::new(
+            "user".to_string(),
+            "password".to_string(),
+            "http://localhost".to_string(),
+        )),
+    ));
+    *app_state.last_run_data.lock().await = Some(RunDataSample::now(run_data()));
+    let rocket = rocket::build()
+        .manage(app_state)
+        .mount("/", routes::charge_mode::routes())
+        .mount("/", routes::events::routes());
+
+    Client::tracked(rocket)
+        .await
+        .expect("valid rocket instance")
+}
+
+/// Parse the `data:` lines of an SSE body
+fn parse_events(body: &str) -> Vec<ControllerEvent> {
+    body.lines()
+        .filter_map(|line| line.strip_prefix("data:"))
+        .map(|data| serde_json::from_str(data.trim()).expect("parse event"))
+        .collect()
+}
+
+#[rocket::async_test]
+async fn test_event_stream() {
+    let client = create_client().await;
+
+    let response = client.get("/events").dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.content_type(), Some(ContentType::EventStream));
+
+    // events from the control loop and mode changes are pushed to the subscriber
+    let state = client.rocket().state::<Arc<AppState>>().unwrap();
+    state.emit(ControllerEvent::ChargePower {
+        timestamp: Local::now(),
+        power: 600.0,
+    });
+    let payload = json!({ "mode": "self-sufficient", "battery_level": 20 });
+    client
+        .post("/charge-mode")
+        .header(ContentType::JSON)
+        .body(payload.to_string())
+        .dispatch()
+        .await;
+    client.rocket().shutdown().notify();
+
+    let body = response.into_string().await.expect("response into string");
+    assert!(body.contains("event:run-data"));
+    assert!(body.contains("event:charge-power"));
+    let events = parse_events(&body);
+    assert_eq!(events.len(), 3);
+    match &events[0] {
+        ControllerEvent::RunData { data, .. } => assert_eq!(data.batterySoc, 42.0),
+        other => panic!("Expected the latest run data first, got {:?}", other),
+    }
+    match &events[1] {
+        ControllerEvent::ChargePower { power, .. } => assert_eq!(*power, 600.0),
+        other => panic!("Expected charge power, got {:?}", other),
+    }
+    match &events[2] {
+        ControllerEvent::ModeChange {
+            mode: ChargeMode::SelfSufficient { battery_level },
+            ..
+        } => assert_eq!(*battery_level, 20),
+        other => panic!("Expected a mode change, got {:?}", other),
+    }
+}
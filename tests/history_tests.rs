@@ -0,0 +1,191 @@
+use chrono::{Local, TimeZone};
+use ecactus_controller::config::AppConfig;
+use ecactus_controller::ecos::client::EcosClient;
+use ecactus_controller::ecos::data_models::RunData;
+use ecactus_controller::history::{EnergyTotals, PowerPoint, RunDataSample};
+use ecactus_controller::routes;
+use ecactus_controller::state::AppState;
+use ecactus_controller::storage::JsonLines;
+use rocket::http::{ContentType, Status};
+use rocket::local::asynchronous::Client;
+use rocket::serde::json::serde_json;
+use std::sync::Arc;
+
+fn run_data(solar_power: f32, grid_power: f32) -> RunData {
+    RunData {
+        batterySoc: 60.0,
+        batteryPower: 0.0,
+        epsPower: 0.0,
+        gridPower: grid_power,
+        homePower: 500.0,
+        meterPower: 0.0,
+        solarPower: solar_power,
+        sysRunMode: 1,
+        isExistSolar: true,
+        sysPowerConfig: 3,
+    }
+}
+
+async fn get_app_state(history: JsonLines<RunDataSample>) -> Arc<AppState> {
+    // two hours of samples every 10 minutes, starting at 2025-01-01 08:00
+    for i in 0..12 {
+        let sample = RunDataSample {
+            timestamp: Local.with_ymd_and_hms(2025, 1, 1, 8, 0, 0).unwrap()
+                + chrono::Duration::minutes(i * 10),
+            data: run_data(1200.0, -600.0),
+        };
+        history.append(&sample).await.expect("append sample");
+    }
+    Arc::new(AppState {
+        history,
+        ..AppState::new(
+            AppConfig::new(),
+            Arc::new(EcosClient::new(
+                "user".to_string(),
+                "password".to_string(),
+                "http://localhost".to_string(),
+            )),
+        )
+    })
+}
+
+async fn create_client(app_state: Arc<AppState>) -> Client {
+    let rocket = rocket::build()
+        .manage(app_state)
+        .mount("/", routes::history::routes())
+        .mount("/", routes::export::routes());
+
+    Client::tracked(rocket)
+        .await
+        .expect("valid rocket instance")
+}
+
+#[rocket::async_test]
+async fn test_get_history_by_hour() {
+    let client = create_client(get_app_state(JsonLines::in_memory()).await).await;
+
+    let response = client
+        .get("/history?from=2025-01-01&to=2025-01-02&resolution=hour")
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+
+    let body = response.into_string().await.expect("response into string");
+    let points: Vec<PowerPoint> = serde_json::from_str(&body).expect("parse power series");
+    assert_eq!(points.len(), 2);
+    assert_eq!(points[0].samples, 6);
+    assert_eq!(points[0].solarPower, 1200.0);
+}
+
+#[rocket::async_test]
+async fn test_get_history_out_of_range() {
+    let client = create_client(get_app_state(JsonLines::in_memory()).await).await;
+
+    let response = client
+        .get("/history?from=2025-01-02&to=2025-01-03")
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.into_string().await.unwrap(), "[]");
+}
+
+#[rocket::async_test]
+async fn test_get_energy_by_day_from_file() {
+    let dir = tempfile::tempdir().expect("temp dir");
+    let history = JsonLines::open_monthly(dir.path().join("history.jsonl"));
+    let client = create_client(get_app_state(history).await).await;
+
+    let response = client
+        .get("/history/energy?from=2025-01-01T00:00:00Z&to=2025-01-03T00:00:00Z&resolution=day")
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+
+    let body = response.into_string().await.expect("response into string");
+    let totals: Vec<EnergyTotals> = serde_json::from_str(&body).expect("parse energy totals");
+    assert_eq!(totals.len(), 1);
+    // 11 intervals of 10 minutes
+    let hours = 110.0 / 60.0;
+    assert!((totals[0].pvGeneration - 1.2 * hours).abs() < 1e-6);
+    assert!((totals[0].gridExport - 0.6 * hours).abs() < 1e-6);
+    assert_eq!(totals[0].gridImport, 0.0);
+    assert!((totals[0].homeConsumption - 0.5 * hours).abs() < 1e-6);
+}
+
+#[rocket::async_test]
+async fn test_history_file_per_month() {
+    let dir = tempfile::tempdir().expect("temp dir");
+    let history = JsonLines::open_monthly(dir.path().join("history.jsonl"));
+    for (month, solar_power) in [(1, 1000.0), (2, 2000.0)] {
+        let sample = RunDataSample {
+            timestamp: Local.with_ymd_and_hms(2025, month, 15, 12, 0, 0).unwrap(),
+            data: run_data(solar_power, 0.0),
+        };
+        history.append(&sample).await.expect("append sample");
+    }
+    assert!(dir.path().join("history-2025-01.jsonl").exists());
+    assert!(dir.path().join("history-2025-02.jsonl").exists());
+    assert!(!dir.path().join("history.jsonl").exists());
+
+    // reading a directory fails, so this shows that months out of range are not opened
+    std::fs::create_dir(dir.path().join("history-2025-03.jsonl")).expect("create dir");
+    let samples = history
+        .read_range(
+            Local.with_ymd_and_hms(2025, 2, 1, 0, 0, 0).unwrap(),
+            Local.with_ymd_and_hms(2025, 3, 1, 0, 0, 0).unwrap(),
+        )
+        .await
+        .expect("read range");
+    assert_eq!(samples.len(), 1);
+    assert_eq!(samples[0].data.solarPower, 2000.0);
+
+    std::fs::remove_dir(dir.path().join("history-2025-03.jsonl")).expect("remove dir");
+    assert_eq!(history.read_all().await.expect("read all").len(), 2);
+}
+
+#[rocket::async_test]
+async fn test_get_energy_rejects_raw_resolution() {
+    let client = create_client(get_app_state(JsonLines::in_memory()).await).await;
+
+    let response = client
+        .get("/history/energy?resolution=raw")
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::BadRequest);
+}
+
+#[rocket::async_test]
+async fn test_export_run_data_csv() {
+    let client = create_client(get_app_state(JsonLines::in_memory()).await).await;
+
+    let response = client
+        .get("/export/run-data?from=2025-01-01&to=2025-01-02&format=csv")
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.content_type(), Some(ContentType::CSV));
+
+    let body = response.into_string().await.expect("response into string");
+    let mut lines = body.lines();
+    assert_eq!(
+        lines.next(),
+        Some("timestamp,batterySoc,batteryPower,epsPower,gridPower,homePower,meterPower,solarPower,sysRunMode,isExistSolar,sysPowerConfig")
+    );
+    assert_eq!(lines.count(), 12);
+}
+
+#[rocket::async_test]
+async fn test_export_run_data_parquet() {
+    let client = create_client(get_app_state(JsonLines::in_memory()).await).await;
+
+    let response = client
+        .get("/export/run-data?from=2025-01-01&to=2025-01-02&format=parquet")
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+
+    let body = response.into_bytes().await.expect("response into bytes");
+    // parquet files start and end with the magic bytes
+    assert_eq!(&body[..4], b"PAR1");
+    assert_eq!(&body[body.len() - 4..], b"PAR1");
+}
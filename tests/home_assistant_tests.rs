@@ -0,0 +1,144 @@
+mod common;
+
+use common::broker::Broker;
+use ecactus_controller::config::{AppConfig, MqttConfig};
+use ecactus_controller::ecos::client::EcosClient;
+use ecactus_controller::home_assistant::Controls;
+use ecactus_controller::mqtt::{self, Mqtt};
+use ecactus_controller::state::{AppState, ChargeMode};
+use rocket::serde::json::{serde_json, Value};
+use rocket::tokio;
+use std::sync::Arc;
+use std::time::Duration;
+
+async fn start_app(broker: &Broker) -> Arc<AppState> {
+    let (mqtt_client, eventloop) = Mqtt::new(MqttConfig {
+        port: broker.port,
+        host: "127.0.0.1".to_string(),
+        discovery: true,
+        ..MqttConfig::default()
+    });
+    let app_state = Arc::new(AppState {
+        mqtt: Some(mqtt_client),
+        ..AppState::new(
+            AppConfig::new(),
+            Arc::new(EcosClient::new(
+                "user".to_string(),
+                "password".to_string(),
+                "http://localhost".to_string(),
+            )),
+        )
+    });
+    tokio::spawn(mqtt::run(app_state.clone(), eventloop));
+    app_state
+}
+
+#[rocket::async_test]
+async fn test_discovery_messages() {
+    let broker = Broker::start().await;
+    let _app_state = start_app(&broker).await;
+
+    let message = broker
+        .wait_for("homeassistant/select/ecactus/charge_mode/config")
+        .await;
+    assert!(message.retain);
+    let config: Value = serde_json::from_slice(&message.payload).expect("parse config");
+    assert_eq!(config["command_topic"], "ecactus/controls/mode/set");
+    assert_eq!(config["state_topic"], "ecactus/charge-mode");
+    // every mode, so that the select can show whichever is running
+    assert_eq!(config["options"].as_array().unwrap().len(), 8);
+    assert_eq!(config["options"][7], "backup");
+
+    let message = broker
+        .wait_for("homeassistant/sensor/ecactus/battery_soc/config")
+        .await;
+    let config: Value = serde_json::from_slice(&message.payload).expect("parse config");
+    assert_eq!(config["value_template"], "{{ value_json.batterySoc }}");
+    assert_eq!(config["device"]["identifiers"][0], "ecactus_123456");
+
+    for number in ["battery_level", "side_load", "duration"] {
+        broker
+            .wait_for(&format!("homeassistant/number/ecactus/{}/config", number))
+            .await;
+    }
+    let message = broker
+        .wait_for("homeassistant/number/ecactus/side_load/config")
+        .await;
+    let config: Value = serde_json::from_slice(&message.payload).expect("parse config");
+    assert_eq!(config["max"], 10_000);
+    let message = broker.wait_for("ecactus/controls").await;
+    let controls: Controls = serde_json::from_slice(&message.payload).expect("parse controls");
+    assert_eq!(controls, Controls::default());
+}
+
+#[rocket::async_test]
+async fn test_select_mode_uses_number_values() {
+    let broker = Broker::start().await;
+    let app_state = start_app(&broker).await;
+    // the controller subscribes before announcing the entities
+    broker
+        .wait_for("homeassistant/select/ecactus/charge_mode/config")
+        .await;
+
+    broker
+        .publish("ecactus/controls/duration/set", "45.0")
+        .await;
+    broker
+        .publish("ecactus/controls/side_load/set", "800")
+        .await;
+    broker.publish("ecactus/controls/mode/set", "active").await;
+
+    let mut current_mode = None;
+    for _ in 0..50 {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let mode = app_state.current_mode.lock().await.clone();
+        if let ChargeMode::Active { .. } = mode {
+            current_mode = Some(mode);
+            break;
+        }
+    }
+    if let Some(ChargeMode::Active {
+        side_load,
+        duration,
+        check_interval,
+        ..
+    }) = current_mode
+    {
+        assert_eq!(side_load, 800);
+        assert_eq!(duration, 45);
+        assert_eq!(check_interval, None);
+    } else {
+        panic!("Expected Active mode");
+    }
+}
+
+#[rocket::async_test]
+async fn test_select_mode_without_controls_is_ignored() {
+    let broker = Broker::start().await;
+    let app_state = start_app(&broker).await;
+    broker
+        .wait_for("homeassistant/select/ecactus/charge_mode/config")
+        .await;
+
+    broker.publish("ecactus/controls/mode/set", "backup").await;
+    broker
+        .publish("ecactus/controls/mode/set", "conservative")
+        .await;
+
+    // the commands are handled in order, so the second one shows that the first was handled
+    for _ in 0..50 {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        if let ChargeMode::Conservative { .. } = *app_state.current_mode.lock().await {
+            break;
+        }
+    }
+    let modes: Vec<&str> = app_state
+        .mode_history
+        .read_all()
+        .await
+        .expect("mode history")
+        .iter()
+        .map(|change| change.mode.name())
+        .collect();
+    assert_eq!(modes, ["conservative"]);
+}
@@ -0,0 +1,39 @@
+use ecactus_controller::config::AppConfig;
+use ecactus_controller::ecos::client::EcosClient;
+use ecactus_controller::routes;
+use ecactus_controller::state::AppState;
+use rocket::http::Status;
+use rocket::local::asynchronous::Client;
+use std::sync::Arc;
+
+#[rocket::async_test]
+async fn test_get_metrics() {
+    let app_state = Arc::new(AppState::new(
+        AppConfig::new(),
+        Arc::new(EcosClient::new(
+            "user".to_string(),
+            "password".to_string(),
+            "http://localhost".to_string(),
+        )),
+    ));
+    // a failed request to the unreachable ECOS server is still counted
+    assert!(app_state.ecos_client.get_devices().await.is_err());
+
+    let rocket = rocket::build()
+        .manage(app_state)
+        .mount("/", routes::metrics::routes());
+    let client = Client::tracked(rocket)
+        .await
+        .expect("valid rocket instance");
+
+    let response = client.get("/metrics").dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+
+    let body = response.into_string().await.expect("response into string");
+    assert!(body.contains("ecactus_charge_mode{mode=\"self-sufficient\"} 1"));
+    assert!(body.contains("ecactus_charge_mode{mode=\"active\"} 0"));
+    assert!(body.contains("ecactus_charge_mode_remaining_seconds 0"));
+    assert!(body.contains("ecactus_background_task_running 0"));
+    assert!(body.contains("ecactus_ecos_errors_total{endpoint=\"devices\"} 1"));
+    assert!(body.contains("ecactus_ecos_logins_total 1"));
+}
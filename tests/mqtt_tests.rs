@@ -0,0 +1,126 @@
+mod common;
+
+use common::broker::Broker;
+use ecactus_controller::config::{AppConfig, MqttConfig};
+use ecactus_controller::ecos::client::EcosClient;
+use ecactus_controller::ecos::data_models::RunData;
+use ecactus_controller::mqtt::{self, Mqtt};
+use ecactus_controller::state::{AppState, ChargeMode};
+use rocket::serde::json::{json, serde_json};
+use rocket::tokio;
+use std::sync::Arc;
+use std::time::Duration;
+
+async fn start_app(broker: &Broker) -> Arc<AppState> {
+    let (mqtt_client, eventloop) = Mqtt::new(MqttConfig {
+        port: broker.port,
+        host: "127.0.0.1".to_string(),
+        ..MqttConfig::default()
+    });
+    let app_state = Arc::new(AppState {
+        mqtt: Some(mqtt_client),
+        ..AppState::new(
+            AppConfig::new(),
+            Arc::new(EcosClient::new(
+                "user".to_string(),
+                "password".to_string(),
+                "http://localhost".to_string(),
+            )),
+        )
+    });
+    tokio::spawn(mqtt::run(app_state.clone(), eventloop));
+    app_state
+}
+
+#[rocket::async_test]
+async fn test_publish_run_data_and_mode() {
+    let broker = Broker::start().await;
+    let app_state = start_app(&broker).await;
+    let mqtt = app_state.mqtt.as_ref().unwrap();
+
+    let run_data = RunData {
+        batterySoc: 55.0,
+        batteryPower: 100.0,
+        epsPower: 0.0,
+        gridPower: -200.0,
+        homePower: 400.0,
+        meterPower: 0.0,
+        solarPower: 700.0,
+        sysRunMode: 1,
+        isExistSolar: true,
+        sysPowerConfig: 3,
+    };
+    mqtt.publish_run_data(&run_data).await;
+    mqtt.publish_mode(&app_state.current_mode.lock().await.clone())
+        .await;
+
+    let message = broker.wait_for("ecactus/run-data").await;
+    let published: RunData = serde_json::from_slice(&message.payload).expect("parse run data");
+    assert_eq!(published.batterySoc, 55.0);
+    assert!(message.retain);
+
+    let message = broker.wait_for("ecactus/charge-mode").await;
+    let published: ChargeMode = serde_json::from_slice(&message.payload).expect("parse mode");
+    assert!(matches!(
+        published,
+        ChargeMode::SelfSufficient { battery_level: 10 }
+    ));
+}
+
+#[rocket::async_test]
+async fn test_command_topic_sets_mode() {
+    let broker = Broker::start().await;
+    let app_state = start_app(&broker).await;
+
+    // wait until the controller has subscribed to the command topic
+    let mut subscribed = false;
+    for _ in 0..50 {
+        broker
+            .publish(
+                "ecactus/charge-mode/set",
+                &json!({ "mode": "conservative", "battery_level": 70, "duration": 30 }).to_string(),
+            )
+            .await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        if let ChargeMode::Conservative { .. } = *app_state.current_mode.lock().await {
+            subscribed = true;
+            break;
+        }
+    }
+    assert!(
+        subscribed,
+        "Expected the command to switch to conservative mode"
+    );
+
+    let message = broker.wait_for("ecactus/charge-mode").await;
+    let published: ChargeMode = serde_json::from_slice(&message.payload).expect("parse mode");
+    if let ChargeMode::Conservative {
+        battery_level,
+        duration,
+        ..
+    } = published
+    {
+        assert_eq!(battery_level, 70);
+        assert_eq!(duration, 30);
+    } else {
+        panic!("Expected Conservative mode");
+    }
+}
+
+#[rocket::async_test]
+async fn test_invalid_command_is_ignored() {
+    let broker = Broker::start().await;
+    let app_state = start_app(&broker).await;
+
+    for _ in 0..10 {
+        broker
+            .publish("ecactus/charge-mode/set", "{\"mode\": \"turbo\"}")
+            .await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    assert!(matches!(
+        *app_state.current_mode.lock().await,
+        ChargeMode::SelfSufficient { .. }
+    ));
+    assert!(broker.messages("ecactus/charge-mode").await.is_empty());
+}
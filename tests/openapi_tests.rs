@@ -0,0 +1,54 @@
+use ecactus_controller::routes;
+use ecactus_controller::state::ChargeMode;
+use rocket::http::Status;
+use rocket::local::asynchronous::Client;
+use rocket::serde::json::Value;
+
+async fn create_client() -> Client {
+    let rocket = rocket::build().mount("/", routes::openapi::routes());
+    Client::tracked(rocket)
+        .await
+        .expect("valid rocket instance")
+}
+
+#[rocket::async_test]
+async fn test_openapi_json() {
+    let client = create_client().await;
+
+    let response = client.get("/openapi.json").dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    let spec: Value = response.into_json().await.expect("OpenAPI document");
+
+    assert!(spec["openapi"].as_str().unwrap().starts_with("3."));
+    for path in [
+        "/charge-mode",
+        "/charge-mode/reset",
+        "/ecos/devices",
+        "/ecos/run-data",
+        "/ecos/charge-mode-settings",
+    ] {
+        assert!(spec["paths"][path].is_object(), "missing path {}", path);
+    }
+    assert!(spec["paths"]["/charge-mode"]["post"]["responses"]["422"].is_object());
+
+    let schemas = &spec["components"]["schemas"];
+    let modes: Vec<&str> = schemas["ChargeMode"]["oneOf"]
+        .as_array()
+        .expect("ChargeMode variants")
+        .iter()
+        .filter_map(|variant| variant["properties"]["mode"]["enum"][0].as_str())
+        .collect();
+    assert_eq!(modes, ChargeMode::NAMES);
+    assert!(schemas["RunDataResponse"].is_object());
+    assert!(schemas["ChargeModeSettingsResponse"].is_object());
+}
+
+#[rocket::async_test]
+async fn test_docs_page() {
+    let client = create_client().await;
+
+    let response = client.get("/docs/").dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    let body = response.into_string().await.expect("response into string");
+    assert!(body.contains("swagger"));
+}
@@ -0,0 +1,115 @@
+mod common;
+
+use common::ecos::MockEcos;
+use ecactus_controller::config::{AppConfig, CacheConfig};
+use ecactus_controller::ecos::client::EcosClient;
+use ecactus_controller::routes;
+use ecactus_controller::snapshots::{SettingsDiff, SettingsSnapshot};
+use ecactus_controller::state::AppState;
+use rocket::http::{ContentType, Status};
+use rocket::local::asynchronous::Client;
+use rocket::serde::json::{json, serde_json, Value};
+use std::sync::Arc;
+
+async fn create_client(ecos: &MockEcos) -> Client {
+    // with the default cache, so that snapshots and diffs must read the device
+    let cache = CacheConfig::default();
+    let app_state = Arc::new(AppState::new(
+        AppConfig::new(),
+        Arc::new(
+            EcosClient::new(
+                "user".to_string(),
+                "password".to_string(),
+                ecos.base_url.clone(),
+            )
+            .with_cache(&cache),
+        ),
+    ));
+    let rocket = rocket::build()
+        .manage(app_state)
+        .mount("/", routes::settings::routes());
+    Client::tracked(rocket)
+        .await
+        .expect("valid rocket instance")
+}
+
+async fn save(client: &Client, name: &str) -> Status {
+    client
+        .post("/settings/snapshots")
+        .header(ContentType::JSON)
+        .body(json!({ "name": name }).to_string())
+        .dispatch()
+        .await
+        .status()
+}
+
+#[rocket::async_test]
+async fn test_snapshot_diff_and_restore() {
+    let ecos = MockEcos::start().await;
+    let client = create_client(&ecos).await;
+
+    assert_eq!(save(&client, "before").await, Status::Ok);
+    // a change in the vendor app while the settings are cached
+    ecos.set_settings(json!({ "minCapacity": 20 })).await;
+    assert_eq!(save(&client, "experiment").await, Status::Ok);
+    assert_eq!(save(&client, "a/b").await, Status::UnprocessableEntity);
+    assert_eq!(
+        save(&client, "with space").await,
+        Status::UnprocessableEntity
+    );
+    let response = client
+        .post("/settings/snapshots")
+        .header(ContentType::JSON)
+        .body("{\"name\":")
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::UnprocessableEntity);
+    let body: Value = response.into_json().await.expect("validation error");
+    assert_eq!(body["errors"][0]["field"], "body");
+
+    let response = client.get("/settings/snapshots").dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    let snapshots: Vec<SettingsSnapshot> =
+        serde_json::from_str(&response.into_string().await.unwrap()).unwrap();
+    let names: Vec<&str> = snapshots.iter().map(|s| s.name.as_str()).collect();
+    assert_eq!(names, ["before", "experiment"]);
+    assert_eq!(snapshots[0].settings.minCapacity, 10);
+
+    let response = client
+        .get("/settings/snapshots/before/diff")
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    let diff: Vec<SettingsDiff> =
+        serde_json::from_str(&response.into_string().await.unwrap()).unwrap();
+    assert_eq!(
+        diff,
+        vec![SettingsDiff {
+            field: "minCapacity".to_string(),
+            snapshot: json!(10),
+            live: json!(20),
+        }]
+    );
+
+    let response = client
+        .post("/settings/snapshots/before/restore")
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(ecos.settings().await["minCapacity"], 10);
+    let posted = ecos.posted().await;
+    assert_eq!(posted.len(), 1);
+    assert_eq!(posted[0]["deviceId"], "123456");
+
+    let response = client
+        .get("/settings/snapshots/before/diff")
+        .dispatch()
+        .await;
+    assert_eq!(response.into_string().await.unwrap(), "[]");
+
+    let response = client
+        .post("/settings/snapshots/missing/restore")
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::NotFound);
+}
@@ -0,0 +1,113 @@
+mod common;
+
+use common::ecos::MockEcos;
+use ecactus_controller::audit::Actor;
+use ecactus_controller::config::AppConfig;
+use ecactus_controller::ecos::client::EcosClient;
+use ecactus_controller::routes;
+use ecactus_controller::state::{AppState, ChargeMode};
+use rocket::http::Status;
+use rocket::local::asynchronous::Client;
+use rocket::serde::json::Value;
+use rocket::tokio;
+use std::sync::Arc;
+use std::time::Duration;
+
+async fn create_client(base_url: &str) -> Client {
+    let app_state = Arc::new(AppState::new(
+        AppConfig::new(),
+        Arc::new(EcosClient::new(
+            "user".to_string(),
+            "password".to_string(),
+            base_url.to_string(),
+        )),
+    ));
+    let rocket = rocket::build()
+        .manage(app_state)
+        .mount("/", routes::status::routes());
+    Client::tracked(rocket)
+        .await
+        .expect("valid rocket instance")
+}
+
+async fn get_status(client: &Client) -> Value {
+    let response = client.get("/status").dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    response.into_json().await.expect("status")
+}
+
+/// Wait until the background task has posted the settings or failed
+async fn wait_for_settings(client: &Client) -> Value {
+    for _ in 0..50 {
+        let status = get_status(client).await;
+        if !status["settings"].is_null() || !status["error"].is_null() {
+            return status;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("The background task did not post any settings");
+}
+
+#[rocket::async_test]
+async fn test_status_of_active_mode() {
+    let ecos = MockEcos::start().await;
+    let client = create_client(&ecos.base_url).await;
+
+    let status = get_status(&client).await;
+    assert_eq!(status["mode"]["mode"], "self-sufficient");
+    assert_eq!(status["task"], "none");
+    assert!(status["remaining_seconds"].is_null());
+
+    let state = client.rocket().state::<Arc<AppState>>().unwrap();
+    AppState::apply_mode(
+        state,
+        ChargeMode::Active {
+            side_load: 0,
+            duration: 30,
+            until: None,
+            check_interval: None,
+            controller: None,
+        },
+        Actor::controller("test"),
+    )
+    .await;
+
+    let status = wait_for_settings(&client).await;
+    assert_eq!(status["mode"]["mode"], "active");
+    assert_eq!(status["task"], "running");
+    assert_eq!(status["healthy"], true);
+    let remaining = status["remaining_seconds"].as_u64().unwrap();
+    assert!(remaining > 29 * 60 && remaining <= 30 * 60);
+    assert!(status["started_at"].is_string());
+    assert!(status["expires_at"].is_string());
+    // (2 × 500 W PV) - 500 W home load
+    assert_eq!(status["charge_power"], 500.0);
+    assert_eq!(status["settings"]["chargeUseMode"], 1);
+    assert_eq!(status["settings"]["chargingList"][0]["power"], 500);
+    assert!(status["error"].is_null());
+
+    state.cancel_task().await;
+    let status = get_status(&client).await;
+    assert_eq!(status["task"], "none");
+}
+
+#[rocket::async_test]
+async fn test_status_reports_last_error() {
+    let client = create_client("http://127.0.0.1:9").await;
+
+    let state = client.rocket().state::<Arc<AppState>>().unwrap();
+    AppState::apply_mode(
+        state,
+        ChargeMode::SelfSufficient { battery_level: 20 },
+        Actor::controller("test"),
+    )
+    .await;
+
+    let status = wait_for_settings(&client).await;
+    assert!(status["settings"].is_null());
+    assert!(status["error"]
+        .as_str()
+        .unwrap()
+        .starts_with("Failed to update charge mode"));
+    assert!(status["error_at"].is_string());
+}